@@ -1,17 +1,27 @@
+use std::cmp::Ordering;
 use std::collections::{BTreeSet, HashMap};
+use std::fmt::Display;
 use std::process::Command;
-
-use anyhow::Result;
-use reqwest::Client;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use hyper::body::Bytes;
+use reqwest::header::{ACCEPT, ETAG, IF_NONE_MATCH, USER_AGENT};
+use reqwest::{Client, Method, RequestBuilder, StatusCode, Url};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+use crate::glob;
+use crate::manifest::Manifest;
+
 /// Dispatch content types
 ///
 /// The purpose of this type is to map dispatch content types to UEFI content
 /// types. This means that GitHub can only select a subset of assets as
 /// dispatch targets. Dispatch will then automatically handle the mapping to
 /// the correct content type for UEFI.
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Type {
     /// An EFI module
     #[serde(rename = "application/vnd.dispatch+efi")]
@@ -37,6 +47,55 @@ impl Type {
     }
 }
 
+/// A release to load assets from
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Source {
+    pub owner: String,
+    pub repo: String,
+    pub tag: String,
+
+    /// Filter asset names
+    #[serde(skip)]
+    pub filter: Vec<String>,
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    /// Parse a source written as `OWNER/REPO@TAG[:FILTER,...]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (release, filter) = s.split_once(':').unwrap_or((s, ""));
+        let parsed = release
+            .split_once('@')
+            .and_then(|(repo, tag)| Some((repo.split_once('/')?, tag)));
+
+        let Some(((owner, repo), tag)) = parsed else {
+            return Err(format!("expected OWNER/REPO@TAG[:FILTER,...]: {s}"));
+        };
+
+        if owner.is_empty() || repo.is_empty() || tag.is_empty() {
+            return Err(format!("expected OWNER/REPO@TAG[:FILTER,...]: {s}"));
+        }
+
+        Ok(Self {
+            owner: owner.into(),
+            repo: repo.into(),
+            tag: tag.into(),
+            filter: filter
+                .split(',')
+                .filter(|f| !f.is_empty())
+                .map(String::from)
+                .collect(),
+        })
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}@{}", self.owner, self.repo, self.tag)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
 #[serde(untagged)]
 enum Knowable<K, U> {
@@ -53,7 +112,7 @@ impl<K, U> Knowable<K, U> {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Asset<T = Type> {
     pub name: String,
     pub size: u64,
@@ -63,6 +122,18 @@ pub struct Asset<T = Type> {
 
     #[serde(rename = "content_type")]
     pub mime: T,
+
+    /// The digest of the asset contents (e.g. `sha256:...`), if GitHub has one
+    #[serde(default)]
+    pub digest: Option<String>,
+
+    /// The API URL of the asset, which also works for private repositories
+    #[serde(rename = "url", default)]
+    pub api: Option<String>,
+
+    /// The release the asset was loaded from
+    #[serde(default)]
+    pub source: Option<Source>,
 }
 
 impl Asset<Knowable<Type, String>> {
@@ -72,13 +143,78 @@ impl Asset<Knowable<Type, String>> {
             size: self.size,
             url: self.url,
             mime,
+            digest: self.digest,
+            api: self.api,
+            source: self.source,
         })
     }
 }
 
+impl Asset {
+    /// The hex-encoded SHA-256 digest of the asset (if known)
+    pub fn sha256(&self) -> Option<&str> {
+        self.digest.as_deref()?.strip_prefix("sha256:")
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct Release {
-    assets: Vec<Asset<Knowable<Type, String>>>,
+    id: u64,
+    tag_name: String,
+
+    #[serde(default)]
+    draft: bool,
+
+    #[serde(default)]
+    prerelease: bool,
+
+    /// The assets embedded in the release (which GitHub may truncate)
+    assets: Vec<serde::de::IgnoredAny>,
+}
+
+/// A run of digits or of other characters within a tag
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Chunk<'a> {
+    Number(u64),
+    Text(&'a str),
+}
+
+/// Compare tags so that the numbers within them compare by value
+///
+/// This orders version tags the way people expect (e.g. `v2.10` after
+/// `v2.9`).
+fn compare_tags(a: &str, b: &str) -> Ordering {
+    fn chunks(mut tag: &str) -> Vec<Chunk<'_>> {
+        let mut chunks = Vec::new();
+
+        while let Some(first) = tag.chars().next() {
+            let digits = first.is_ascii_digit();
+            let end = tag
+                .find(|c: char| c.is_ascii_digit() != digits)
+                .unwrap_or(tag.len());
+
+            let (chunk, rest) = tag.split_at(end);
+            chunks.push(chunk.parse().map_or(Chunk::Text(chunk), Chunk::Number));
+            tag = rest;
+        }
+
+        chunks
+    }
+
+    chunks(a).cmp(&chunks(b))
+}
+
+/// The dispatch assets of a release along with its manifest
+#[derive(Debug)]
+pub struct Contents {
+    /// The release, with its tag resolved
+    pub source: Source,
+
+    pub assets: BTreeSet<Asset>,
+    pub manifest: Manifest,
+
+    /// Problems noticed while loading the assets
+    pub warnings: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -98,49 +234,123 @@ pub struct Report<M = String> {
     milestone: Option<M>,
 }
 
+impl Report {
+    pub const fn new(title: String, body: String) -> Self {
+        Self {
+            title,
+            body: Some(body),
+            labels: None,
+            assignees: None,
+            milestone: None,
+        }
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn body(&self) -> Option<&str> {
+        self.body.as_deref()
+    }
+
+    /// Add labels and assignees to the issue
+    pub fn tag(&mut self, labels: &[String], assignees: &[String]) {
+        for (list, extra) in [(&mut self.labels, labels), (&mut self.assignees, assignees)] {
+            if !extra.is_empty() {
+                let list = list.get_or_insert_with(Vec::new);
+                for x in extra {
+                    if !list.contains(x) {
+                        list.push(x.clone());
+                    }
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, clap::Args)]
 pub struct GitHubArgs {
     /// GitHub token for API access
     #[arg(long, env = "GITHUB_TOKEN")]
     pub token: Option<String>,
 
+    /// GitHub API URL (for GitHub Enterprise Server, `https://HOST/api/v3`)
+    #[arg(long, env = "GITHUB_API_URL", default_value = "https://api.github.com")]
+    pub api_url: Url,
+
     /// GitHub repository owner
-    #[arg(short = 'o', long)]
-    pub owner: String,
+    #[arg(short = 'o', long, required_unless_present = "sources", requires_all = ["repo", "tag"])]
+    pub owner: Option<String>,
 
     /// GitHub repository name
-    #[arg(short = 'r', long)]
-    pub repo: String,
+    #[arg(short = 'r', long, required_unless_present = "sources", requires_all = ["owner", "tag"])]
+    pub repo: Option<String>,
 
-    /// Release tag to download assets from
-    #[arg(short = 't', long)]
-    pub tag: String,
+    /// Release tag to download assets from (`latest`, `latest-prerelease` or a glob like `v2.*`)
+    #[arg(short = 't', long, required_unless_present = "sources", requires_all = ["owner", "repo"])]
+    pub tag: Option<String>,
 
-    /// Filter asset names
+    /// Another release to download assets from (`OWNER/REPO@TAG[:FILTER,...]`)
+    #[arg(long = "source", value_name = "SOURCE")]
+    pub sources: Vec<Source>,
+
+    /// Filter asset names (of the releases without a filter of their own)
     #[arg(trailing_var_arg = true)]
     pub filter: Vec<String>,
 }
 
 impl GitHubArgs {
+    /// The API URL of the repository of a source
+    fn repo_url(&self, source: &Source) -> String {
+        let api = self.api_url.as_str().trim_end_matches('/');
+        format!("{api}/repos/{}/{}", source.owner, source.repo)
+    }
+
+    /// Every release to download assets from
+    fn sources(&self) -> Vec<Source> {
+        let first = match (&self.owner, &self.repo, &self.tag) {
+            (Some(owner), Some(repo), Some(tag)) => Some(Source {
+                owner: owner.clone(),
+                repo: repo.clone(),
+                tag: tag.clone(),
+                filter: Vec::new(),
+            }),
+            _ => None,
+        };
+
+        first
+            .into_iter()
+            .chain(self.sources.iter().cloned())
+            .map(|mut source| {
+                if source.filter.is_empty() {
+                    source.filter.clone_from(&self.filter);
+                }
+
+                source
+            })
+            .collect()
+    }
+
     /// Authenticate with GitHub by guiding user to create a Personal Access Token
     pub async fn login(mut self) -> Result<GitHub> {
-        // Try to get token from GitHub CLI
+        // Try to get token from GitHub CLI (for the GitHub Enterprise host, if any)
         if self.token.is_none() {
-            self.token = Command::new("gh")
-                .arg("auth")
-                .arg("token")
-                .output()
-                .ok()
-                .and_then(|output| {
-                    if !output.status.success() {
-                        return None;
-                    }
+            let mut command = Command::new("gh");
+            command.arg("auth").arg("token");
+            if let Some(host) = self.api_url.host_str().filter(|&h| h != "api.github.com") {
+                command.arg("--hostname").arg(host);
+            }
+
+            self.token = command.output().ok().and_then(|output| {
+                if !output.status.success() {
+                    return None;
+                }
 
-                    String::from_utf8(output.stdout)
-                        .ok()
-                        .map(|s| s.trim().to_string())
-                        .filter(|s| !s.is_empty())
-                });
+                String::from_utf8(output.stdout)
+                    .ok()
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+            });
         }
 
         // If we already have a token, create the client.
@@ -185,6 +395,12 @@ impl GitHubArgs {
     }
 }
 
+/// An issue filed on GitHub
+#[derive(Debug, Deserialize)]
+pub struct Issue {
+    pub html_url: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct Milestone {
     title: String,
@@ -195,7 +411,16 @@ struct Milestone {
 pub struct GitHub {
     args: GitHubArgs,
     client: Client,
-    milestones: HashMap<String, u64>,
+    sources: Vec<Source>,
+
+    /// The milestones of each repository, by owner and name
+    milestones: HashMap<(String, String), HashMap<String, u64>>,
+
+    /// The entity tag and body of earlier responses, by URL
+    etags: Mutex<HashMap<String, (String, Bytes)>>,
+
+    /// The manifests downloaded so far, by the API URL of their asset
+    manifests: Mutex<HashMap<String, Bytes>>,
 }
 
 impl GitHub {
@@ -213,59 +438,309 @@ impl GitHub {
         }
 
         let client = Client::builder().default_headers(headers).build()?;
+        let sources = args.sources();
         let mut milestones = HashMap::new();
 
-        // Load all milestones...
+        // Load all milestones of every repository...
+        for source in &sources {
+            let key = (source.owner.clone(), source.repo.clone());
+            if milestones.contains_key(&key) {
+                continue;
+            }
+
+            let mut titles = HashMap::new();
+            for n in 1.. {
+                let url = format!(
+                    "{}/milestones?state=all&per_page={}&page={}",
+                    args.repo_url(source),
+                    Self::PER_PAGE,
+                    n
+                );
+
+                let response = client.get(&url).send().await?;
+                let page: Vec<Milestone> = response.json().await?;
+                if page.is_empty() {
+                    break;
+                }
+
+                for milestone in page {
+                    titles.insert(milestone.title, milestone.number);
+                }
+            }
+
+            milestones.insert(key, titles);
+        }
+
+        Ok(Self {
+            args,
+            client,
+            sources,
+            milestones,
+            etags: Mutex::new(HashMap::new()),
+            manifests: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Get JSON from the API, revalidating earlier responses by their entity tag
+    ///
+    /// GitHub doesn't count conditional requests answered with `304 Not
+    /// Modified` against the rate limit, which keeps watching releases cheap.
+    async fn get<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        let cached = self.etags.lock().unwrap().get(url).cloned();
+
+        let mut request = self.client.get(url);
+        if let Some((etag, ..)) = &cached {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+
+        let response = request.send().await?;
+        if let (StatusCode::NOT_MODIFIED, Some((.., body))) = (response.status(), cached) {
+            return Ok(serde_json::from_slice(&body)?);
+        }
+
+        let response = response.error_for_status()?;
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+
+        let body = response.bytes().await?;
+        if let Some(etag) = etag {
+            let mut etags = self.etags.lock().unwrap();
+            etags.insert(url.to_string(), (etag, body.clone()));
+        }
+
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Download the manifest of a release, unless it was downloaded before
+    ///
+    /// The API URL of an asset names its id, which changes whenever the
+    /// asset is uploaded again, so a manifest under the same URL never
+    /// changes. This keeps watching releases from downloading it each time.
+    async fn manifest<T: Sync>(&self, asset: &Asset<T>) -> Result<Bytes> {
+        let key = asset.api.as_ref().unwrap_or(&asset.url);
+        if let Some(bytes) = self.manifests.lock().unwrap().get(key) {
+            return Ok(bytes.clone());
+        }
+
+        let request = self.download(&self.client, Method::GET, asset);
+        let bytes = async { request.send().await?.error_for_status()?.bytes().await }
+            .await
+            .with_context(|| format!("Failed to download {}", Manifest::NAME))?;
+
+        self.manifests
+            .lock()
+            .unwrap()
+            .insert(key.clone(), bytes.clone());
+        Ok(bytes)
+    }
+
+    /// The host of the GitHub API
+    pub fn host(&self) -> Option<&str> {
+        self.args.api_url.host_str()
+    }
+
+    /// Build a request to download an asset using the given client
+    ///
+    /// With a token, the asset is downloaded through the release assets API
+    /// so that assets of private repositories can be downloaded. GitHub
+    /// answers with a redirect to a short-lived signed URL. When following
+    /// it to another host, reqwest drops the `Authorization` header, which
+    /// the signed URL would otherwise reject.
+    pub fn download<T>(&self, client: &Client, method: Method, asset: &Asset<T>) -> RequestBuilder {
+        match (&self.args.token, &asset.api) {
+            (Some(token), Some(api)) => client
+                .request(method, api)
+                .bearer_auth(token)
+                .header(ACCEPT, "application/octet-stream")
+                .header(USER_AGENT, Self::USER_AGENT),
+
+            _ => client.request(method, &asset.url),
+        }
+    }
+
+    /// Load the dispatch assets of every release along with their manifests
+    pub async fn assets(&self) -> Result<Vec<Contents>> {
+        let mut releases = Vec::new();
+        for source in &self.sources {
+            let contents = self
+                .release(source)
+                .await
+                .with_context(|| format!("Failed to load release {source}"))?;
+
+            releases.push(contents);
+        }
+
+        Ok(releases)
+    }
+
+    /// Find the release of a source
+    ///
+    /// Besides exact tags, the tag may be `latest` (the latest full
+    /// release), `latest-prerelease` (the latest prerelease) or a glob
+    /// matching the tags of full releases, of which the highest version is
+    /// picked. Draft releases are never picked.
+    async fn find(&self, source: &Source) -> Result<Release> {
+        let repo_url = self.args.repo_url(source);
+        let tag = source.tag.as_str();
+
+        let exact = match tag {
+            "latest" => Some(format!("{repo_url}/releases/latest")),
+            "latest-prerelease" => None,
+            tag if tag.contains(['*', '?']) => None,
+            tag => Some(format!("{repo_url}/releases/tags/{tag}")),
+        };
+
+        if let Some(url) = exact {
+            return self.get(&url).await;
+        }
+
+        // Look through the list of releases, newest first.
+        let mut matching = Vec::new();
+        for n in 1.. {
+            let url = format!("{repo_url}/releases?per_page={}&page={}", Self::PER_PAGE, n);
+
+            let page: Vec<Release> = self.get(&url).await?;
+            if page.is_empty() {
+                break;
+            }
+
+            for release in page.into_iter().filter(|release| !release.draft) {
+                if tag == "latest-prerelease" {
+                    if release.prerelease {
+                        return Ok(release);
+                    }
+                } else if !release.prerelease && glob::matches(tag, &release.tag_name) {
+                    matching.push(release);
+                }
+            }
+        }
+
+        matching
+            .into_iter()
+            .max_by(|a, b| compare_tags(&a.tag_name, &b.tag_name))
+            .with_context(|| format!("No release matches {tag}"))
+    }
+
+    /// Load the dispatch assets of a release that was published or edited
+    ///
+    /// Only the sources whose tag chooses the release load it, so nothing
+    /// is loaded for releases of other repositories or with other tags.
+    pub async fn released(&self, owner: &str, repo: &str, id: u64) -> Result<Vec<Contents>> {
+        let mut releases = Vec::new();
+        for source in &self.sources {
+            if !source.owner.eq_ignore_ascii_case(owner) || !source.repo.eq_ignore_ascii_case(repo)
+            {
+                continue;
+            }
+
+            let release = self.find(source).await?;
+            if release.id == id {
+                releases.push(self.load(source, &release).await?);
+            }
+        }
+
+        Ok(releases)
+    }
+
+    /// Find and load the dispatch assets of a release along with its manifest
+    async fn release(&self, source: &Source) -> Result<Contents> {
+        let release = self.find(source).await?;
+        self.load(source, &release).await
+    }
+
+    /// Load the dispatch assets of a release along with its manifest
+    async fn load(&self, source: &Source, release: &Release) -> Result<Contents> {
+        let source = &Source {
+            tag: release.tag_name.clone(),
+            ..source.clone()
+        };
+
+        let repo_url = self.args.repo_url(source);
+
+        // The assets embedded in the release are capped, so page through
+        // the assets of the release instead.
+        let mut all = Vec::new();
         for n in 1.. {
             let url = format!(
-                "https://api.github.com/repos/{}/{}/milestones?state=all&per_page={}&page={}",
-                args.owner,
-                args.repo,
+                "{repo_url}/releases/{}/assets?per_page={}&page={}",
+                release.id,
                 Self::PER_PAGE,
                 n
             );
 
-            let response = client.get(&url).send().await?;
-            let page: Vec<Milestone> = response.json().await?;
+            let page: Vec<Asset<Knowable<Type, String>>> = self.get(&url).await?;
             if page.is_empty() {
                 break;
             }
 
-            for milestone in page {
-                milestones.insert(milestone.title, milestone.number);
-            }
+            all.extend(page);
         }
 
-        Ok(Self {
-            args,
-            client,
-            milestones,
-        })
-    }
+        let mut warnings = Vec::new();
+        if all.len() != release.assets.len() {
+            warnings.push(format!(
+                "Release {source} lists {} assets but {} were found",
+                release.assets.len(),
+                all.len()
+            ));
+        }
 
-    pub async fn assets(&self) -> Result<BTreeSet<Asset>> {
-        let url = format!(
-            "https://api.github.com/repos/{}/{}/releases/tags/{}",
-            self.args.owner, self.args.repo, self.args.tag
-        );
+        let manifest = match all.iter().find(|asset| asset.name == Manifest::NAME) {
+            Some(asset) => Some(self.manifest(asset).await?),
+            None => None,
+        };
 
-        let response = self.client.get(&url).send().await?;
-        let release: Release = response.json().await?;
+        // The filter isn't saved in the state file, so it is left out of the
+        // assets' source for them to compare equal after resuming.
+        let origin = Source {
+            filter: Vec::new(),
+            ..source.clone()
+        };
 
-        let assets = release
-            .assets
+        let known = all
             .into_iter()
             .filter_map(Asset::known)
+            .map(|asset| Asset {
+                source: Some(origin.clone()),
+                ..asset
+            })
+            .collect::<Vec<_>>();
+
+        // The manifest is validated against every asset of the release, so
+        // that filtering the assets doesn't invalidate it.
+        let manifest = match manifest {
+            None => Manifest::default(),
+            Some(bytes) => Manifest::parse(&bytes, &known)?,
+        };
+
+        let assets = known
+            .into_iter()
             .filter(|asset| {
-                self.args.filter.is_empty()
-                    || self.args.filter.iter().any(|f| asset.name.contains(f))
+                source.filter.is_empty() || source.filter.iter().any(|f| asset.name.contains(f))
             })
             .collect::<BTreeSet<_>>();
 
-        Ok(assets)
+        Ok(Contents {
+            source: source.clone(),
+            assets,
+            manifest,
+            warnings,
+        })
     }
 
-    pub async fn report(&self, report: Report) -> Result<()> {
+    /// File an issue in the repository of the source (or of the first source)
+    pub async fn report(&self, source: Option<&Source>, report: Report) -> Result<Issue> {
+        let source = source
+            .or_else(|| self.sources.first())
+            .context("No repository to file the issue in")?;
+
+        let milestones = self
+            .milestones
+            .get(&(source.owner.clone(), source.repo.clone()));
+
         let report = Report {
             title: report.title,
             body: report.body,
@@ -275,17 +750,12 @@ impl GitHub {
             // We make a best-effort attempt to add the milestone. But if the
             // milestone isn't found on the repo, we still file an issue so
             // that we don't lose the results of the test run.
-            milestone: report
-                .milestone
-                .and_then(|t| self.milestones.get(&t).copied()),
+            milestone: report.milestone.and_then(|t| milestones?.get(&t).copied()),
         };
 
-        let url = format!(
-            "https://api.github.com/repos/{}/{}/issues",
-            self.args.owner, self.args.repo
-        );
+        let url = format!("{}/issues", self.args.repo_url(source));
 
-        self.client.post(&url).json(&report).send().await?;
-        Ok(())
+        let response = self.client.post(&url).json(&report).send().await?;
+        Ok(response.error_for_status()?.json().await?)
     }
 }
@@ -0,0 +1,70 @@
+/// Match text against a glob pattern
+///
+/// A `*` matches any run of characters and a `?` matches any single
+/// character. All other characters match themselves.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut star = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+
+            // Backtrack: let the last `*` swallow one more character.
+            _ => match star {
+                Some((sp, st)) => {
+                    star = Some((sp, st + 1));
+                    p = sp + 1;
+                    t = st + 1;
+                }
+
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn literal() {
+        assert!(matches("v1.0", "v1.0"));
+        assert!(!matches("v1.0", "v1.01"));
+        assert!(!matches("", "v1"));
+        assert!(matches("", ""));
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("v?.0", "v1.0"));
+        assert!(!matches("v?.0", "v10.0"));
+        assert!(matches("*", ""));
+        assert!(matches("v1.*", "v1."));
+        assert!(matches("v1.**", "v1.2"));
+    }
+
+    #[test]
+    fn backtracking() {
+        assert!(matches("*ab", "aab"));
+        assert!(matches("a*b*c", "axbxbyc"));
+        assert!(matches("*a*b", "xaxaxb"));
+        assert!(matches("v*-rc?", "v2-rc-rc1"));
+        assert!(!matches("a*b*c", "axbxbyd"));
+        assert!(!matches("*ab", "abba"));
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
+use hyper::StatusCode as Code;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::jobs::{Job, Jobs, State};
+
+/// The JSON representation of a job
+#[derive(Serialize)]
+struct View<'a> {
+    name: &'a str,
+    source: Option<String>,
+    run: u32,
+    runs: u32,
+    host: Option<IpAddr>,
+    pinned: Option<IpAddr>,
+    state: &'static str,
+    ip: Option<IpAddr>,
+    seen: Option<String>,
+    size: u64,
+    downloaded: u64,
+    attempt: u32,
+    attempts: u32,
+    failure: Option<String>,
+    issue: Option<&'a str>,
+}
+
+impl<'a> From<&'a Job> for View<'a> {
+    fn from(job: &'a Job) -> Self {
+        Self {
+            name: &job.asset.name,
+            source: job.asset.source.as_ref().map(ToString::to_string),
+            run: job.run,
+            runs: job.runs,
+            host: job.host,
+            pinned: job.pinned,
+            state: job.state.name(),
+            ip: job.state.ip(),
+            seen: job
+                .seen
+                .map(|time| DateTime::<Utc>::from(time).to_rfc3339()),
+            size: job.asset.size,
+            downloaded: job.downloaded,
+            attempt: job.attempt,
+            attempts: job.attempts,
+            failure: match job.state {
+                State::Failed(.., failure) => Some(failure.to_string()),
+                _ => None,
+            },
+            issue: job.issue.as_deref(),
+        }
+    }
+}
+
+/// Answer a read-only API request, returning `None` for unknown routes
+///
+/// The routes are relative to `{path}/api/`:
+///
+///   * `jobs`: every job
+///   * `jobs/{name}`: the jobs for the named asset
+///   * `summary`: the number of jobs in each state
+pub fn get(jobs: &Jobs, route: &str) -> Option<Vec<u8>> {
+    let json = match route.trim_end_matches('/') {
+        "jobs" => serde_json::to_vec(&jobs.iter().map(View::from).collect::<Vec<_>>()),
+
+        "summary" => {
+            let mut summary: BTreeMap<&str, usize> = [
+                "total",
+                "unassigned",
+                "assigned",
+                "downloading",
+                "booting",
+                "reported",
+                "finished",
+                "failed",
+                "cancelled",
+            ]
+            .into_iter()
+            .map(|name| (name, 0))
+            .collect();
+
+            for job in jobs.iter() {
+                *summary.entry(job.state.name()).or_default() += 1;
+                *summary.entry("total").or_default() += 1;
+            }
+
+            serde_json::to_vec(&summary)
+        }
+
+        route => {
+            let name = route.strip_prefix("jobs/")?;
+            let matching = jobs
+                .iter()
+                .filter(|job| job.asset.name == name)
+                .map(View::from)
+                .collect::<Vec<_>>();
+
+            if matching.is_empty() {
+                return None;
+            }
+
+            serde_json::to_vec(&matching)
+        }
+    };
+
+    json.ok()
+}
+
+/// Perform an administrative action, returning `None` for unknown routes
+///
+/// The routes are relative to `{path}/api/`:
+///
+///   * `pause`: stop handing out new jobs
+///   * `unpause`: start handing out new jobs again
+///   * `drain`: stop handing out new jobs and exit once running jobs end
+///   * `jobs/{name}/requeue`: requeue the failed or stuck jobs for an asset
+///   * `jobs/{name}/cancel`: cancel the unfinished jobs for an asset
+///   * `hosts/{ip}/release`: return the jobs held by a host to the queue
+pub fn post(jobs: &mut Jobs, route: &str) -> Option<(Code, Vec<u8>)> {
+    let route = route.trim_end_matches('/');
+
+    match route {
+        "pause" => jobs.pause(true),
+        "unpause" => jobs.pause(false),
+        "drain" => jobs.drain(),
+
+        route => {
+            let (target, action) = route.rsplit_once('/')?;
+
+            let count = match (target.split_once('/')?, action) {
+                (("jobs", name), "requeue") if jobs.contains(name) => {
+                    jobs.requeue(|job| job.asset.name == name)
+                }
+
+                (("jobs", name), "cancel") if jobs.contains(name) => {
+                    jobs.cancel(|job| job.asset.name == name)
+                }
+
+                (("hosts", ip), "release") => match ip.parse() {
+                    Ok(ip) => jobs.release(ip),
+                    Err(..) => return Some((Code::BAD_REQUEST, Vec::new())),
+                },
+
+                _ => return None,
+            };
+
+            // Nothing was in a state that the action applies to.
+            let code = if count == 0 { Code::CONFLICT } else { Code::OK };
+            return Some((code, json!({ "jobs": count }).to_string().into_bytes()));
+        }
+    }
+
+    let json = json!({ "paused": jobs.paused(), "draining": jobs.draining() });
+    Some((Code::OK, json.to_string().into_bytes()))
+}
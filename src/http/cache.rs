@@ -0,0 +1,125 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use futures_util::StreamExt;
+use reqwest::{Client, Method};
+use sha2::{Digest, Sha256};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+use crate::github::{Asset, GitHub};
+
+/// A content-addressed, on-disk cache of assets
+///
+/// Assets with a known SHA-256 digest are stored under that digest. Assets
+/// without one are stored under the digest of their API URL, which names the
+/// asset's id and so changes whenever the asset is uploaded again. Either
+/// way, an asset is only moved into the cache once its size (and digest,
+/// when known) has been verified.
+pub struct Cache {
+    dir: PathBuf,
+    client: Client,
+    github: Arc<GitHub>,
+    filling: Mutex<HashSet<PathBuf>>,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf, client: Client, github: Arc<GitHub>) -> Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create cache directory {}", dir.display()))?;
+
+        Ok(Self {
+            dir,
+            client,
+            github,
+            filling: Mutex::new(HashSet::new()),
+        })
+    }
+
+    fn key(asset: &Asset) -> String {
+        asset.sha256().map_or_else(
+            || {
+                let url = asset.api.as_deref().unwrap_or(&asset.url);
+                format!("url-{:x}", Sha256::digest(url.as_bytes()))
+            },
+            |digest| format!("sha256-{digest}"),
+        )
+    }
+
+    fn path(&self, asset: &Asset) -> PathBuf {
+        self.dir.join(Self::key(asset))
+    }
+
+    /// The entity tag of the cached copy of an asset
+    pub fn etag(asset: &Asset) -> String {
+        format!("\"{}\"", Self::key(asset))
+    }
+
+    /// Open the cached copy of an asset (if any)
+    pub async fn open(&self, asset: &Asset) -> Option<File> {
+        let file = File::open(self.path(asset)).await.ok()?;
+        let metadata = file.metadata().await.ok()?;
+        (metadata.len() == asset.size).then_some(file)
+    }
+
+    /// Download an asset into the cache
+    ///
+    /// If the asset is already cached, or is being downloaded by another
+    /// task, this returns immediately.
+    pub async fn fill(&self, asset: &Asset) -> Result<()> {
+        let path = self.path(asset);
+        if self.open(asset).await.is_some() {
+            return Ok(());
+        }
+
+        if !self.filling.lock().unwrap().insert(path.clone()) {
+            return Ok(());
+        }
+
+        let result = self.download(asset, &path).await;
+        self.filling.lock().unwrap().remove(&path);
+        result
+    }
+
+    async fn download(&self, asset: &Asset, path: &Path) -> Result<()> {
+        let part = path.with_extension("part");
+        let result = self.receive(asset, &part).await;
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&part).await;
+            return result;
+        }
+
+        tokio::fs::rename(part, path).await?;
+        Ok(())
+    }
+
+    /// Download an asset into a partial file, verifying its size and digest
+    async fn receive(&self, asset: &Asset, part: &Path) -> Result<()> {
+        let mut file = File::create(part).await?;
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+
+        let request = self.github.download(&self.client, Method::GET, asset);
+        let response = request.send().await?;
+        let mut stream = response.error_for_status()?.bytes_stream();
+        while let Some(bytes) = stream.next().await {
+            let bytes = bytes?;
+            hasher.update(&bytes);
+            size += bytes.len() as u64;
+            file.write_all(&bytes).await?;
+        }
+
+        file.sync_all().await?;
+        drop(file);
+
+        let digest = format!("{:x}", hasher.finalize());
+        let verified = size == asset.size && asset.sha256().is_none_or(|d| d == digest);
+        if !verified {
+            anyhow::bail!("Failed to verify {} (got {size} bytes)", asset.name);
+        }
+
+        Ok(())
+    }
+}
@@ -1,4 +1,9 @@
+mod api;
+mod cache;
+mod range;
 mod server;
 mod service;
+mod webhook;
 
 pub use server::Server;
+pub use webhook::Webhook;
@@ -0,0 +1,201 @@
+use hyper::header::{HeaderMap, IF_RANGE, RANGE};
+use hyper::http::response::Builder;
+use hyper::StatusCode as Code;
+
+/// An inclusive range of bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Range {
+    pub const fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Parse a `Range` header value against a resource of the given size
+    ///
+    /// Only single ranges are supported. Multiple ranges (or any other
+    /// syntax we don't understand) are ignored, which causes the whole
+    /// resource to be sent as permitted by RFC 9110.
+    fn parse(value: &str, size: u64) -> Option<Selection> {
+        let spec = value.trim().strip_prefix("bytes=")?;
+        if spec.contains(',') {
+            return None;
+        }
+
+        let (start, end) = spec.split_once('-')?;
+        let (start, end) = match (start.trim(), end.trim()) {
+            // A suffix range: the last N bytes
+            ("", n) => {
+                let n: u64 = n.parse().ok()?;
+                if n == 0 || size == 0 {
+                    return Some(Selection::Unsatisfiable);
+                }
+
+                (size.saturating_sub(n), size - 1)
+            }
+
+            // An open range: from N to the end
+            (n, "") => (n.parse().ok()?, size.saturating_sub(1)),
+
+            // A closed range: from N to M
+            (n, m) => {
+                let start: u64 = n.parse().ok()?;
+                let end: u64 = m.parse().ok()?;
+                if end < start {
+                    return None;
+                }
+
+                (start, end.min(size.saturating_sub(1)))
+            }
+        };
+
+        if start >= size {
+            return Some(Selection::Unsatisfiable);
+        }
+
+        Some(Selection::Partial(Self { start, end }))
+    }
+}
+
+/// The part of a locally-served resource that a client asked for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    Full,
+    Partial(Range),
+    Unsatisfiable,
+}
+
+impl Selection {
+    /// Select the part of a resource requested by the `Range` header
+    ///
+    /// An `If-Range` header that doesn't match the resource's `etag`
+    /// means the client's partial copy is stale, so the full resource
+    /// is selected.
+    pub fn new(headers: &HeaderMap, size: u64, etag: &str) -> Self {
+        let Some(range) = headers.get(RANGE).and_then(|v| v.to_str().ok()) else {
+            return Self::Full;
+        };
+
+        if let Some(tag) = headers.get(IF_RANGE) {
+            if tag.as_bytes() != etag.as_bytes() {
+                return Self::Full;
+            }
+        }
+
+        Range::parse(range, size).unwrap_or(Self::Full)
+    }
+
+    /// The range to send, or `None` for an empty body
+    pub const fn range(&self, size: u64) -> Option<Range> {
+        match self {
+            Self::Full if size > 0 => Some(Range {
+                start: 0,
+                end: size - 1,
+            }),
+            Self::Partial(range) => Some(*range),
+            Self::Full | Self::Unsatisfiable => None,
+        }
+    }
+
+    /// Start a response for this selection of a resource
+    pub fn respond(&self, size: u64, etag: &str) -> Builder {
+        let builder = Builder::new()
+            .header("accept-ranges", "bytes")
+            .header("etag", etag);
+
+        match self {
+            Self::Full => builder.status(Code::OK).header("content-length", size),
+
+            Self::Partial(range) => builder
+                .status(Code::PARTIAL_CONTENT)
+                .header("content-length", range.len())
+                .header(
+                    "content-range",
+                    format!("bytes {}-{}/{size}", range.start, range.end),
+                ),
+
+            Self::Unsatisfiable => builder
+                .status(Code::RANGE_NOT_SATISFIABLE)
+                .header("content-length", 0)
+                .header("content-range", format!("bytes */{size}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::HeaderValue;
+
+    use super::*;
+
+    const fn partial(start: u64, end: u64) -> Selection {
+        Selection::Partial(Range { start, end })
+    }
+
+    #[test]
+    fn suffix() {
+        assert_eq!(Range::parse("bytes=-10", 100), Some(partial(90, 99)));
+        assert_eq!(Range::parse("bytes=-200", 100), Some(partial(0, 99)));
+        assert_eq!(
+            Range::parse("bytes=-0", 100),
+            Some(Selection::Unsatisfiable)
+        );
+    }
+
+    #[test]
+    fn open() {
+        assert_eq!(Range::parse("bytes=10-", 100), Some(partial(10, 99)));
+        assert_eq!(Range::parse("bytes=99-", 100), Some(partial(99, 99)));
+    }
+
+    #[test]
+    fn closed() {
+        assert_eq!(Range::parse("bytes=10-19", 100), Some(partial(10, 19)));
+        assert_eq!(Range::parse("bytes=90-200", 100), Some(partial(90, 99)));
+        assert_eq!(Range { start: 10, end: 19 }.len(), 10);
+    }
+
+    #[test]
+    fn unsatisfiable() {
+        assert_eq!(
+            Range::parse("bytes=100-", 100),
+            Some(Selection::Unsatisfiable)
+        );
+        assert_eq!(
+            Range::parse("bytes=100-199", 100),
+            Some(Selection::Unsatisfiable)
+        );
+    }
+
+    #[test]
+    fn ignored() {
+        assert_eq!(Range::parse("bytes=20-10", 100), None);
+        assert_eq!(Range::parse("bytes=0-9,20-29", 100), None);
+        assert_eq!(Range::parse("lines=0-9", 100), None);
+        assert_eq!(Range::parse("bytes=x-9", 100), None);
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(Range::parse("bytes=0-", 0), Some(Selection::Unsatisfiable));
+        assert_eq!(Range::parse("bytes=-5", 0), Some(Selection::Unsatisfiable));
+        assert_eq!(Selection::Full.range(0), None);
+        assert_eq!(Selection::Full.range(1), Some(Range { start: 0, end: 0 }));
+    }
+
+    #[test]
+    fn if_range() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RANGE, HeaderValue::from_static("bytes=0-9"));
+        assert_eq!(Selection::new(&headers, 100, "\"a\""), partial(0, 9));
+
+        headers.insert(IF_RANGE, HeaderValue::from_static("\"a\""));
+        assert_eq!(Selection::new(&headers, 100, "\"a\""), partial(0, 9));
+
+        headers.insert(IF_RANGE, HeaderValue::from_static("\"b\""));
+        assert_eq!(Selection::new(&headers, 100, "\"a\""), Selection::Full);
+    }
+}
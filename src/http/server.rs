@@ -1,5 +1,7 @@
+use std::path::PathBuf;
 use std::sync::Arc;
 
+use anyhow::Result;
 use hyper::server::conn::http1::Builder;
 use hyper_util::rt::TokioIo;
 use reqwest::redirect::Policy;
@@ -7,7 +9,9 @@ use reqwest::Client;
 use tokio::net::TcpListener;
 use tokio::sync::Mutex;
 
+use super::cache::Cache;
 use super::service::Service;
+use super::webhook::Webhook;
 use crate::github::GitHub;
 use crate::tui::Status;
 
@@ -17,18 +21,37 @@ pub struct Server {
     github: Arc<GitHub>,
     client: Client,
     path: Arc<String>,
+    cache: Option<Arc<Cache>>,
+    admin: Option<Arc<String>>,
+    webhook: Option<Arc<Webhook>>,
 }
 
 impl Server {
     const REDIRECTS: usize = 2;
     const DOMAINS: &[&str] = &["githubusercontent.com"];
 
+    /// Create the server
+    ///
+    /// Asset downloads may be redirected to `DOMAINS`, to the host of the
+    /// GitHub API (and its subdomains) and to the extra `domains`.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         listener: TcpListener,
         status: Arc<Mutex<Status>>,
         github: Arc<GitHub>,
         path: Arc<String>,
-    ) -> reqwest::Result<Self> {
+        cache: Option<PathBuf>,
+        admin: Option<String>,
+        webhook: Option<Webhook>,
+        domains: Vec<String>,
+    ) -> Result<Self> {
+        let domains: Vec<String> = Self::DOMAINS
+            .iter()
+            .map(ToString::to_string)
+            .chain(github.host().map(str::to_string))
+            .chain(domains)
+            .collect();
+
         let policy = Policy::custom(move |attempt| {
             if attempt.previous().len() > Self::REDIRECTS {
                 return attempt.stop();
@@ -38,7 +61,7 @@ impl Server {
                 return attempt.stop();
             };
 
-            for domain in Self::DOMAINS {
+            for domain in &domains {
                 if let Some(prefix) = host.strip_suffix(domain) {
                     if prefix.is_empty() || prefix.ends_with('.') {
                         return attempt.follow();
@@ -49,15 +72,50 @@ impl Server {
             attempt.stop()
         });
 
+        let client = Client::builder().redirect(policy).build()?;
+        let cache = cache
+            .map(|dir| Cache::new(dir, client.clone(), github.clone()).map(Arc::new))
+            .transpose()?;
+
         Ok(Self {
             listener,
             status,
             github,
-            client: Client::builder().redirect(policy).build()?,
+            client,
             path,
+            cache,
+            admin: admin.map(Arc::new),
+            webhook: webhook.map(Arc::new),
         })
     }
 
+    /// Download all assets into the cache in the background
+    pub async fn prefetch(&self) {
+        let Some(cache) = self.cache.clone() else {
+            return;
+        };
+
+        let assets = self
+            .status
+            .lock()
+            .await
+            .jobs()
+            .assets()
+            .into_iter()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let status = self.status.clone();
+        tokio::spawn(async move {
+            for asset in assets {
+                if let Err(error) = cache.fill(&asset).await {
+                    let warning = format!("Failed to cache {}: {error:#}", asset.name);
+                    status.lock().await.warn(warning);
+                }
+            }
+        });
+    }
+
     pub async fn serve(self) -> std::io::Result<()> {
         loop {
             // Accept a new connection.
@@ -66,11 +124,23 @@ impl Server {
             let github = self.github.clone();
             let client = self.client.clone();
             let path = self.path.clone();
+            let cache = self.cache.clone();
+            let admin = self.admin.clone();
+            let webhook = self.webhook.clone();
 
             // Spawn a new task to handle the connection.
             tokio::spawn(async move {
                 let stream = TokioIo::new(stream);
-                let service = Service::new(addr.ip(), status, github, client, path);
+                let service = Service::new(
+                    addr.ip(),
+                    status,
+                    github,
+                    client,
+                    path,
+                    cache,
+                    admin,
+                    webhook,
+                );
                 Builder::new().serve_connection(stream, service).await
             });
         }
@@ -1,41 +1,57 @@
 use std::future::Future;
+use std::io;
 use std::net::IpAddr;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
 
 use futures_util::{stream, StreamExt};
-use http_body_util::{combinators::BoxBody, BodyExt, StreamBody};
+use http_body_util::{combinators::BoxBody, BodyExt, LengthLimitError, Limited, StreamBody};
 use hyper::body::{Bytes, Frame, Incoming};
+use hyper::header::{HeaderMap, AUTHORIZATION, IF_RANGE, RANGE};
 use hyper::{Method, StatusCode as Code};
 use hyper::{Request, Response};
 use reqwest::Client;
-use std::convert::Infallible;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom, Take};
 use tokio::sync::Mutex;
 
+use super::api;
+use super::cache::Cache;
+use super::range::Selection;
+use super::webhook::Webhook;
 use crate::github::{Asset, GitHub, Report, Type};
-use crate::tui::Status;
+use crate::tui::{self, Status};
 
 // This contains the bytes of the poweroff.efi module.
 const POWEROFF_EFI: &[u8] = include_bytes!(env!("POWEROFF_BIN_PATH"));
+const POWEROFF_ETAG: &str = concat!("\"poweroff-", env!("CARGO_PKG_VERSION"), "\"");
 const EMPTY: &[u8] = &[];
 
 /// Main HTTP service that handles all requests
+#[derive(Clone)]
 pub struct Service {
     remote: IpAddr,
     status: Arc<Mutex<Status>>,
     github: Arc<GitHub>,
     client: Client,
     path: Arc<String>,
+    cache: Option<Arc<Cache>>,
+    admin: Option<Arc<String>>,
+    webhook: Option<Arc<Webhook>>,
 }
 
 impl Service {
+    #[allow(clippy::too_many_arguments)]
     pub const fn new(
         remote: IpAddr,
         status: Arc<Mutex<Status>>,
         github: Arc<GitHub>,
         client: Client,
         path: Arc<String>,
+        cache: Option<Arc<Cache>>,
+        admin: Option<Arc<String>>,
+        webhook: Option<Arc<Webhook>>,
     ) -> Self {
         Self {
             remote,
@@ -43,147 +59,428 @@ impl Service {
             github,
             client,
             path,
+            cache,
+            admin,
+            webhook,
         }
     }
 }
 
 impl hyper::service::Service<Request<Incoming>> for Service {
-    type Response = Response<BoxBody<Bytes, Infallible>>;
+    type Response = Response<BoxBody<Bytes, io::Error>>;
     type Error = anyhow::Error;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
 
     fn call(&self, req: Request<Incoming>) -> Self::Future {
-        let status = self.status.clone();
-        let client = self.client.clone();
-        let github = self.github.clone();
-        let path = self.path.clone();
-        let remote = self.remote;
-
+        let service = self.clone();
         Box::pin(async move {
-            if req.uri().path() != *path {
-                return Ok(EMPTY.reply(Code::NOT_FOUND, None, None));
+            let method = req.method().clone();
+            let path = req.uri().path().to_string();
+            let remote = service.remote;
+
+            let response = service.handle(req).await;
+            match &response {
+                Ok(response) => tui::log(
+                    "request",
+                    &[
+                        ("method", &method),
+                        ("path", &path),
+                        ("remote", &remote),
+                        ("status", &response.status().as_u16()),
+                    ],
+                ),
+
+                Err(error) => tui::log(
+                    "request",
+                    &[
+                        ("method", &method),
+                        ("path", &path),
+                        ("remote", &remote),
+                        ("error", &format!("{error:#}")),
+                    ],
+                ),
             }
 
-            let (response, ct) = match *req.method() {
-                // The POST request is used to signal the start of a job.
-                Method::POST => {
-                    if status.lock().await.update().booting(remote) {
-                        return Ok(EMPTY.reply(None, None, None));
-                    }
+            response
+        })
+    }
+}
 
-                    return Ok(EMPTY.reply(Code::EXPECTATION_FAILED, None, None));
-                }
+impl Service {
+    async fn handle(
+        self,
+        req: Request<Incoming>,
+    ) -> anyhow::Result<Response<BoxBody<Bytes, io::Error>>> {
+        let Self {
+            remote,
+            status,
+            github,
+            client,
+            path,
+            cache,
+            ..
+        } = self.clone();
 
-                // The PUT request is used to report completion of a job.
-                Method::PUT => {
-                    // Collect the request body
-                    let bytes = req.into_body().collect().await?.to_bytes();
-                    let report: Report = match serde_json::from_slice(&bytes) {
-                        Err(..) => return Ok(EMPTY.reply(Code::BAD_REQUEST, None, None)),
-                        Ok(report) => report,
-                    };
-
-                    // Display that the job has been reported.
-                    if !status.lock().await.update().report(remote) {
-                        return Ok(EMPTY.reply(Code::EXPECTATION_FAILED, None, None));
-                    }
+        // Receive GitHub webhook deliveries under the boot path.
+        if req.uri().path() == format!("{path}/webhook") {
+            return self.webhook(req).await;
+        }
 
-                    // Create a GitHub issue for the report.
-                    let reported = tokio::time::Instant::now();
-                    if github.report(report).await.is_err() {
-                        return Ok(EMPTY.reply(Code::INTERNAL_SERVER_ERROR, None, None));
-                    }
+        // Serve the API under the boot path.
+        let api = format!("{path}/api/");
+        if let Some(route) = req.uri().path().strip_prefix(&api) {
+            let route = route.to_string();
+            return self.api(&req, &route).await;
+        }
 
-                    // Mark the job as finished.
-                    tokio::spawn(async move {
-                        tokio::time::sleep_until(reported + Duration::from_secs(5)).await;
-                        status.lock().await.update().finish(remote);
-                    });
+        if req.uri().path() != *path {
+            return Ok(EMPTY.reply(Code::NOT_FOUND, None, None));
+        }
 
+        let (response, ct) = match *req.method() {
+            // The POST request is used to signal the start of a job.
+            Method::POST => {
+                if status.lock().await.update().booting(remote) {
                     return Ok(EMPTY.reply(None, None, None));
                 }
 
-                // The HEAD request is used to get information about the assigned asset.
-                Method::HEAD => {
-                    match status.clone().assign(remote).await {
-                        // No asset assigned, return poweroff EFI binary.
-                        None => return Ok(POWEROFF_EFI.reply(None, Type::Efi, EMPTY)),
+                return Ok(EMPTY.reply(Code::EXPECTATION_FAILED, None, None));
+            }
+
+            // The PUT request is used to report completion of a job.
+            Method::PUT => return self.report(req).await,
+
+            // The HEAD request is used to get information about the assigned asset.
+            Method::HEAD => {
+                match status.clone().assign(remote, false).await {
+                    // No asset assigned, return poweroff EFI binary.
+                    None => {
+                        return POWEROFF_EFI
+                            .serve(req.headers(), &Type::Efi, POWEROFF_ETAG, false)
+                            .await
+                    }
+
+                    Some(asset) => {
+                        // Serve the asset from the cache, if we have it.
+                        if let Some(file) = cache.open(&asset).await {
+                            let etag = Cache::etag(&asset);
+                            return file.serve(req.headers(), &asset.mime, &etag, false).await;
+                        }
 
                         // Send the request (possibly redirecting...)
-                        Some(asset) => (client.head(asset.url).send().await?, asset.mime),
+                        let request = github.download(&client, Method::HEAD, &asset);
+                        let request = request.headers(req.headers().ranges());
+                        (request.send().await?, asset.mime)
                     }
                 }
+            }
+
+            // The GET request is used to fetch the assigned asset.
+            Method::GET => {
+                // A ranged request may be resuming an interrupted download.
+                let resume = req.headers().contains_key(RANGE);
+                match status.clone().assign(remote, resume).await {
+                    // No asset assigned, return poweroff EFI binary.
+                    None => {
+                        return POWEROFF_EFI
+                            .serve(req.headers(), &Type::Efi, POWEROFF_ETAG, true)
+                            .await
+                    }
 
-                // The GET request is used to fetch the assigned asset.
-                Method::GET => {
-                    match status.clone().assign(remote).await {
-                        // No asset assigned, return poweroff EFI binary.
-                        None => return Ok(POWEROFF_EFI.reply(None, Type::Efi, None)),
+                    Some(asset) => {
+                        // Serve the asset from the cache, if we have it.
+                        let file = cache.open(&asset).await;
+                        let hit = cache.as_ref().map(|_| file.is_some());
+                        status.clone().downloading(remote, hit).await;
+                        if let Some(file) = file {
+                            let etag = Cache::etag(&asset);
+                            let response =
+                                file.serve(req.headers(), &asset.mime, &etag, true).await?;
+                            return Ok(response.map(|body| self.tally(body)));
+                        }
 
                         // Send the request (possibly redirecting...)
-                        Some(asset) => {
-                            let response = client.get(asset.url).send().await?;
-                            status.lock().await.update().downloading(remote);
-                            (response, asset.mime)
-                        }
+                        let request = github.download(&client, Method::GET, &asset);
+                        let request = request.headers(req.headers().ranges());
+                        let response = request.send().await?;
+                        cache.fill(asset.clone(), status.clone());
+                        (response, asset.mime)
                     }
                 }
+            }
+
+            // Bad method.
+            _ => {
+                return Ok(Response::builder()
+                    .status(Code::METHOD_NOT_ALLOWED)
+                    .header("allow", "GET, POST, HEAD, PUT")
+                    .body(EMPTY.embody())?)
+            }
+        };
+
+        let content_type = ct.content_type().parse().unwrap();
+
+        // Construct the response.
+        let mut builder = Response::builder().status(response.status());
+        for (key, mut value) in response.headers() {
+            // GitHub always returns `application/octet-stream` for EFI
+            // binaries, so we override it here.
+            if key == "content-type" {
+                value = &content_type;
+            }
+
+            builder = builder.header(key, value);
+        }
+
+        // Stream the response body directly. An upstream error is passed on,
+        // so that the connection is aborted rather than the body truncated.
+        Ok(builder.body(
+            self.tally(BoxBody::new(StreamBody::new(Box::pin(
+                response
+                    .bytes_stream()
+                    .map(|result| result.map(Frame::data).map_err(io::Error::other)),
+            )))),
+        )?)
+    }
+
+    /// Count the bytes of a download against the job of the remote host
+    fn tally(&self, body: BoxBody<Bytes, io::Error>) -> BoxBody<Bytes, io::Error> {
+        let mut tally = Tally {
+            status: self.status.clone(),
+            ip: self.remote,
+            bytes: 0,
+        };
+
+        BoxBody::new(body.map_frame(move |frame| {
+            if let Some(data) = frame.data_ref() {
+                tally.count(data.len());
+            }
+
+            frame
+        }))
+    }
 
-                // Bad method.
-                _ => {
+    async fn api(
+        self,
+        req: &Request<Incoming>,
+        route: &str,
+    ) -> anyhow::Result<Response<BoxBody<Bytes, io::Error>>> {
+        let reply = match *req.method() {
+            Method::GET => {
+                api::get(self.status.lock().await.jobs(), route).map(|json| (Code::OK, json))
+            }
+
+            // Administrative actions require the admin token.
+            Method::POST => match &self.admin {
+                None => Some((Code::FORBIDDEN, Vec::new())),
+                Some(token) if !req.headers().authorized(token) => {
                     return Ok(Response::builder()
-                        .status(Code::METHOD_NOT_ALLOWED)
-                        .header("allow", "GET, POST, HEAD, PUT")
-                        .body(EMPTY.embody())?)
+                        .status(Code::UNAUTHORIZED)
+                        .header("www-authenticate", "Bearer")
+                        .body(EMPTY.embody())?);
                 }
-            };
 
-            let content_type = ct.content_type().parse().unwrap();
+                Some(..) => api::post(&mut self.status.lock().await.update(), route),
+            },
 
-            // Construct the response.
-            let mut builder = Response::builder().status(response.status());
-            for (key, mut value) in response.headers() {
-                // GitHub always returns `application/octet-stream` for EFI
-                // binaries, so we override it here.
-                if key == "content-type" {
-                    value = &content_type;
-                }
+            _ => {
+                return Ok(Response::builder()
+                    .status(Code::METHOD_NOT_ALLOWED)
+                    .header("allow", "GET, POST")
+                    .body(EMPTY.embody())?)
+            }
+        };
+
+        let Some((code, json)) = reply else {
+            return Ok(EMPTY.reply(Code::NOT_FOUND, None, None));
+        };
+
+        let mut builder = Response::builder()
+            .status(code)
+            .header("content-length", json.len());
 
-                builder = builder.header(key, value);
+        if !json.is_empty() {
+            builder = builder.header("content-type", "application/json");
+        }
+
+        Ok(builder.body(json.embody())?)
+    }
+
+    async fn webhook(
+        self,
+        req: Request<Incoming>,
+    ) -> anyhow::Result<Response<BoxBody<Bytes, io::Error>>> {
+        let Some(webhook) = self.webhook else {
+            return Ok(EMPTY.reply(Code::NOT_FOUND, None, None));
+        };
+
+        if req.method() != Method::POST {
+            return Ok(Response::builder()
+                .status(Code::METHOD_NOT_ALLOWED)
+                .header("allow", "POST")
+                .body(EMPTY.embody())?);
+        }
+
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+
+        let signature = header("x-hub-signature-256");
+        let event = header("x-github-event");
+
+        let body = match Limited::new(req.into_body(), Webhook::LIMIT)
+            .collect()
+            .await
+        {
+            Ok(body) => body.to_bytes(),
+            Err(error) if error.is::<LengthLimitError>() => {
+                return Ok(EMPTY.reply(Code::PAYLOAD_TOO_LARGE, None, None));
             }
 
-            // Stream the response body directly, mapping errors to Infallible
-            Ok(builder.body(BoxBody::new(StreamBody::new(Box::pin(
-                response.bytes_stream().map(|result| {
-                    result.map_or_else(
-                        |_| Ok(Frame::data(Bytes::new())),
-                        |bytes| Ok(Frame::data(bytes)),
-                    )
-                }),
-            ))))?)
-        })
+            Err(error) => return Err(anyhow::Error::from_boxed(error)),
+        };
+
+        // Only deliveries signed with the shared secret are accepted.
+        if !webhook.verify(signature.as_deref(), &body) {
+            return Ok(EMPTY.reply(Code::UNAUTHORIZED, None, None));
+        }
+
+        let code = webhook.deliver(event.as_deref(), &body, self.status, self.github);
+        Ok(EMPTY.reply(code, None, None))
+    }
+
+    async fn report(
+        self,
+        req: Request<Incoming>,
+    ) -> anyhow::Result<Response<BoxBody<Bytes, io::Error>>> {
+        let Self {
+            remote,
+            status,
+            github,
+            ..
+        } = self;
+
+        // Collect the request body
+        let bytes = req.into_body().collect().await?.to_bytes();
+        let mut report: Report = match serde_json::from_slice(&bytes) {
+            Err(..) => return Ok(EMPTY.reply(Code::BAD_REQUEST, None, None)),
+            Ok(report) => report,
+        };
+
+        // Display that the job has been reported.
+        let tags = status
+            .lock()
+            .await
+            .update()
+            .report(remote, &report)
+            .map(|job| {
+                let source = job.asset.source.clone();
+                (job.labels.clone(), job.assignees.clone(), source)
+            });
+
+        let Some((labels, assignees, source)) = tags else {
+            return Ok(EMPTY.reply(Code::EXPECTATION_FAILED, None, None));
+        };
+
+        // Add the labels and assignees from the manifest.
+        report.tag(&labels, &assignees);
+
+        // Create a GitHub issue for the report.
+        let reported = tokio::time::Instant::now();
+        let Ok(issue) = github.report(source.as_ref(), report).await else {
+            status.lock().await.update().unreported(remote);
+            return Ok(EMPTY.reply(Code::INTERNAL_SERVER_ERROR, None, None));
+        };
+
+        status.lock().await.update().filed(remote, issue.html_url);
+
+        // Mark the job as finished.
+        tokio::spawn(async move {
+            tokio::time::sleep_until(reported + Duration::from_secs(5)).await;
+            status.lock().await.update().finish(remote);
+        });
+
+        Ok(EMPTY.reply(None, None, None))
+    }
+}
+
+/// The number of bytes sent to a host, recorded once the body is dropped
+struct Tally {
+    status: Arc<Mutex<Status>>,
+    ip: IpAddr,
+    bytes: u64,
+}
+
+impl Tally {
+    const fn count(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+    }
+}
+
+impl Drop for Tally {
+    fn drop(&mut self) {
+        if self.bytes == 0 {
+            return;
+        }
+
+        let (status, ip, bytes) = (self.status.clone(), self.ip, self.bytes);
+        tokio::spawn(async move { status.lock().await.update().downloaded(ip, bytes) });
     }
 }
 
 trait Embody {
-    fn embody(self) -> BoxBody<Bytes, Infallible>;
+    fn embody(self) -> BoxBody<Bytes, io::Error>;
 }
 
 impl Embody for &'static [u8] {
-    fn embody(self) -> BoxBody<Bytes, Infallible> {
+    fn embody(self) -> BoxBody<Bytes, io::Error> {
         BoxBody::new(StreamBody::new(Box::pin(stream::once(async move {
             Ok(Frame::data(Bytes::from(self)))
         }))))
     }
 }
 
+impl Embody for Vec<u8> {
+    fn embody(self) -> BoxBody<Bytes, io::Error> {
+        BoxBody::new(StreamBody::new(Box::pin(stream::once(async move {
+            Ok(Frame::data(Bytes::from(self)))
+        }))))
+    }
+}
+
+impl Embody for Take<File> {
+    fn embody(self) -> BoxBody<Bytes, io::Error> {
+        const CHUNK: usize = 64 * 1024;
+
+        BoxBody::new(StreamBody::new(Box::pin(stream::unfold(
+            self,
+            |mut file| async move {
+                let mut buf = vec![0; CHUNK];
+                match file.read(&mut buf).await {
+                    Ok(0) => None,
+                    Ok(n) => {
+                        buf.truncate(n);
+                        Some((Ok(Frame::data(Bytes::from(buf))), file))
+                    }
+
+                    // Abort the connection rather than truncate the body.
+                    Err(error) => Some((Err(error), file)),
+                }
+            },
+        ))))
+    }
+}
+
 trait Reply {
     fn reply(
         self,
         code: impl Into<Option<Code>>,
         ct: impl Into<Option<Type>>,
         body: impl Into<Option<&'static [u8]>>,
-    ) -> Response<BoxBody<Bytes, Infallible>>;
+    ) -> Response<BoxBody<Bytes, io::Error>>;
 }
 
 impl Reply for &'static [u8] {
@@ -192,7 +489,7 @@ impl Reply for &'static [u8] {
         code: impl Into<Option<Code>>,
         ct: impl Into<Option<Type>>,
         body: impl Into<Option<&'static [u8]>>,
-    ) -> Response<BoxBody<Bytes, Infallible>> {
+    ) -> Response<BoxBody<Bytes, io::Error>> {
         let mut builder = Response::builder()
             .status(code.into().unwrap_or(Code::OK))
             .header("content-length", self.len());
@@ -205,12 +502,164 @@ impl Reply for &'static [u8] {
     }
 }
 
+/// Serve locally-held content, honoring `Range` and `If-Range` headers
+trait Serve {
+    async fn serve(
+        self,
+        headers: &HeaderMap,
+        ct: &Type,
+        etag: &str,
+        body: bool,
+    ) -> anyhow::Result<Response<BoxBody<Bytes, io::Error>>>;
+}
+
+impl Serve for &'static [u8] {
+    fn serve(
+        self,
+        headers: &HeaderMap,
+        ct: &Type,
+        etag: &str,
+        body: bool,
+    ) -> impl Future<Output = anyhow::Result<Response<BoxBody<Bytes, io::Error>>>> {
+        let size = self.len() as u64;
+        let selection = Selection::new(headers, size, etag);
+
+        #[allow(clippy::cast_possible_truncation)]
+        let content = match selection.range(size) {
+            Some(range) if body => &self[range.start as usize..=range.end as usize],
+            _ => EMPTY,
+        };
+
+        std::future::ready(
+            selection
+                .respond(size, etag)
+                .header("content-type", ct.content_type())
+                .body(content.embody())
+                .map_err(Into::into),
+        )
+    }
+}
+
+impl Serve for File {
+    async fn serve(
+        mut self,
+        headers: &HeaderMap,
+        ct: &Type,
+        etag: &str,
+        body: bool,
+    ) -> anyhow::Result<Response<BoxBody<Bytes, io::Error>>> {
+        let size = self.metadata().await?.len();
+        let selection = Selection::new(headers, size, etag);
+
+        let content = match selection.range(size) {
+            Some(range) if body => {
+                self.seek(SeekFrom::Start(range.start)).await?;
+                self.take(range.len()).embody()
+            }
+
+            _ => EMPTY.embody(),
+        };
+
+        Ok(selection
+            .respond(size, etag)
+            .header("content-type", ct.content_type())
+            .body(content)?)
+    }
+}
+
+/// Extract the headers that should be forwarded upstream for ranged requests
+trait Ranges {
+    fn ranges(&self) -> HeaderMap;
+}
+
+impl Ranges for HeaderMap {
+    fn ranges(&self) -> HeaderMap {
+        let mut headers = Self::new();
+        for name in [RANGE, IF_RANGE] {
+            if let Some(value) = self.get(&name) {
+                headers.insert(name, value.clone());
+            }
+        }
+
+        headers
+    }
+}
+
+/// Check the bearer token of administrative requests
+trait Authorized {
+    fn authorized(&self, token: &str) -> bool;
+}
+
+impl Authorized for HeaderMap {
+    fn authorized(&self, token: &str) -> bool {
+        let Some(bearer) = self
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+        else {
+            return false;
+        };
+
+        // Compare in constant time so the token can't be guessed byte by byte.
+        bearer.len() == token.len()
+            && bearer
+                .bytes()
+                .zip(token.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
+trait Fill {
+    async fn open(&self, asset: &Asset) -> Option<File>;
+    fn fill(&self, asset: Asset, status: Arc<Mutex<Status>>);
+}
+
+impl Fill for Option<Arc<Cache>> {
+    async fn open(&self, asset: &Asset) -> Option<File> {
+        match self {
+            Some(cache) => cache.open(asset).await,
+            None => None,
+        }
+    }
+
+    fn fill(&self, asset: Asset, status: Arc<Mutex<Status>>) {
+        if let Some(cache) = self.clone() {
+            tokio::spawn(async move {
+                if let Err(error) = cache.fill(&asset).await {
+                    let warning = format!("Failed to cache {}: {error:#}", asset.name);
+                    status.lock().await.warn(warning);
+                }
+            });
+        }
+    }
+}
+
 trait Assign {
-    async fn assign(self, ip: IpAddr) -> Option<Asset>;
+    async fn assign(self, ip: IpAddr, resume: bool) -> Option<Asset>;
+    async fn downloading(self, ip: IpAddr, hit: Option<bool>);
 }
 
 impl Assign for Arc<Mutex<Status>> {
-    async fn assign(self, ip: IpAddr) -> Option<Asset> {
-        self.lock().await.update().assign(ip)
+    async fn assign(self, ip: IpAddr, resume: bool) -> Option<Asset> {
+        let resumed = if resume {
+            self.lock().await.update().resume(ip)
+        } else {
+            None
+        };
+
+        match resumed {
+            Some(asset) => Some(asset),
+            None => self.lock().await.update().assign(ip),
+        }
+    }
+
+    async fn downloading(self, ip: IpAddr, hit: Option<bool>) {
+        let mut status = self.lock().await;
+        if let Some(hit) = hit {
+            status.cached(hit);
+        }
+
+        status.update().downloading(ip);
     }
 }
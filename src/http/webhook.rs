@@ -0,0 +1,222 @@
+use std::sync::Arc;
+
+use hmac::{Hmac, Mac};
+use hyper::StatusCode as Code;
+use serde::Deserialize;
+use sha2::Sha256;
+use tokio::sync::Mutex;
+
+use crate::github::GitHub;
+use crate::jobs::JobsArgs;
+use crate::tui::Status;
+
+#[derive(Debug, Deserialize)]
+struct Owner {
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Repository {
+    name: String,
+    owner: Owner,
+}
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    id: u64,
+    tag_name: String,
+
+    #[serde(default)]
+    draft: bool,
+}
+
+/// The payload of a `release` event
+#[derive(Debug, Deserialize)]
+struct Event {
+    action: String,
+    release: Release,
+    repository: Repository,
+}
+
+/// Decode a hex string
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Receives the GitHub webhook deliveries for `release` events
+pub struct Webhook {
+    secret: String,
+    args: JobsArgs,
+}
+
+impl Webhook {
+    /// The largest payload GitHub delivers (in bytes)
+    pub const LIMIT: usize = 25 * 1024 * 1024;
+
+    pub const fn new(secret: String, args: JobsArgs) -> Self {
+        Self { secret, args }
+    }
+
+    /// Whether the `X-Hub-Signature-256` header signs the body with the secret
+    pub fn verify(&self, signature: Option<&str>, body: &[u8]) -> bool {
+        let Some(signature) = signature
+            .and_then(|s| s.strip_prefix("sha256="))
+            .and_then(unhex)
+        else {
+            return false;
+        };
+
+        let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes()) else {
+            return false;
+        };
+
+        mac.update(body);
+        mac.verify_slice(&signature).is_ok()
+    }
+
+    /// Handle a verified delivery, returning the status code to answer with
+    ///
+    /// When a release is published or edited, the jobs for its new assets
+    /// are added in the background, since GitHub expects a quick answer.
+    pub fn deliver(
+        self: Arc<Self>,
+        event: Option<&str>,
+        body: &[u8],
+        status: Arc<Mutex<Status>>,
+        github: Arc<GitHub>,
+    ) -> Code {
+        let event = match Self::release(event, body) {
+            Ok(event) => event,
+            Err(code) => return code,
+        };
+
+        tokio::spawn(async move {
+            let Event {
+                release,
+                repository,
+                ..
+            } = event;
+
+            let owner = &repository.owner.login;
+            let loaded = github.released(owner, &repository.name, release.id).await;
+
+            let mut status = status.lock().await;
+            match loaded {
+                Ok(releases) => status.add(&releases, &self.args),
+                Err(error) => status.warn(format!(
+                    "Failed to load release {owner}/{}@{}: {error:#}",
+                    repository.name, release.tag_name
+                )),
+            }
+        });
+
+        Code::ACCEPTED
+    }
+
+    /// The published or edited release of a delivery, or else the status
+    /// code to answer with
+    fn release(event: Option<&str>, body: &[u8]) -> Result<Event, Code> {
+        match event {
+            Some("ping") => return Err(Code::OK),
+            Some("release") => {}
+            _ => return Err(Code::NO_CONTENT),
+        }
+
+        let event = serde_json::from_slice::<Event>(body).map_err(|_| Code::BAD_REQUEST)?;
+        if !matches!(event.action.as_str(), "published" | "edited") || event.release.draft {
+            return Err(Code::NO_CONTENT);
+        }
+
+        Ok(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct Args {
+        #[command(flatten)]
+        jobs: JobsArgs,
+    }
+
+    fn webhook() -> Webhook {
+        let args = Args::parse_from(["dispatch"]);
+        Webhook::new("It's a Secret to Everybody".into(), args.jobs)
+    }
+
+    // The example from GitHub's documentation on validating deliveries.
+    const SIGNATURE: &str =
+        "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+    #[test]
+    fn accepts_signed() {
+        assert!(webhook().verify(Some(SIGNATURE), b"Hello, World!"));
+    }
+
+    #[test]
+    fn rejects_unsigned() {
+        let webhook = webhook();
+        assert!(!webhook.verify(None, b"Hello, World!"));
+        assert!(!webhook.verify(Some(&SIGNATURE[7..]), b"Hello, World!"));
+        assert!(!webhook.verify(Some(SIGNATURE), b"Hello, World?"));
+        assert!(!webhook.verify(Some(&SIGNATURE[..20]), b"Hello, World!"));
+    }
+
+    // A recorded delivery of a published release.
+    const RELEASE: &str = include_str!("testdata/release.json");
+
+    /// The recorded delivery with some fields of the release changed
+    fn delivery(action: &str, draft: bool) -> Vec<u8> {
+        let mut event: serde_json::Value = serde_json::from_str(RELEASE).unwrap();
+        event["action"] = action.into();
+        event["release"]["draft"] = draft.into();
+        serde_json::to_vec(&event).unwrap()
+    }
+
+    #[test]
+    fn parses_recorded() {
+        let event = Webhook::release(Some("release"), RELEASE.as_bytes()).unwrap();
+        assert_eq!(event.action, "published");
+        assert_eq!(event.release.id, 231_077_146);
+        assert_eq!(event.release.tag_name, "nightly-2025-06-02");
+        assert_eq!(event.repository.owner.login, "AMDEPYC");
+        assert_eq!(event.repository.name, "dispatch");
+    }
+
+    #[test]
+    fn filters_releases() {
+        let release = |action, draft| Webhook::release(Some("release"), &delivery(action, draft));
+
+        assert!(release("published", false).is_ok());
+        assert!(release("edited", false).is_ok());
+        assert_eq!(release("edited", true).unwrap_err(), Code::NO_CONTENT);
+        assert_eq!(release("created", false).unwrap_err(), Code::NO_CONTENT);
+        assert_eq!(release("deleted", false).unwrap_err(), Code::NO_CONTENT);
+    }
+
+    #[test]
+    fn filters_events() {
+        let body = RELEASE.as_bytes();
+        assert_eq!(Webhook::release(Some("ping"), body).unwrap_err(), Code::OK);
+        assert_eq!(
+            Webhook::release(Some("push"), body).unwrap_err(),
+            Code::NO_CONTENT
+        );
+        assert_eq!(Webhook::release(None, body).unwrap_err(), Code::NO_CONTENT);
+        assert_eq!(
+            Webhook::release(Some("release"), b"{}").unwrap_err(),
+            Code::BAD_REQUEST
+        );
+    }
+}
@@ -1,10 +1,17 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::BufReader;
 use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime};
 
-use crate::github::Asset;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+use crate::github::{Asset, Contents, Report, Source};
+use crate::rules::{Host, Rules};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum State {
     Unassigned,
     Assigned(IpAddr),
@@ -12,70 +19,729 @@ pub enum State {
     Booting(IpAddr),
     Reported(IpAddr),
     Finished(IpAddr),
-    Failed(IpAddr),
+    Failed(IpAddr, Failure),
+    Cancelled,
+}
+
+/// Why a job failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Failure {
+    /// The host rebooted before the workload booted
+    NotBooted,
+
+    /// The host rebooted before the workload reported its results
+    NotReported,
+
+    /// The job stayed in one state for longer than its timeout
+    Timeout,
+
+    /// The GitHub issue for the report could not be created
+    ReportFailed,
+
+    /// An operator marked the job as failed
+    Operator,
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::NotBooted => "rebooted before booting",
+            Self::NotReported => "rebooted before reporting",
+            Self::Timeout => "timed out",
+            Self::ReportFailed => "issue creation failed",
+            Self::Operator => "failed by operator",
+        })
+    }
 }
 
 impl State {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Unassigned => "unassigned",
+            Self::Assigned(..) => "assigned",
+            Self::Downloading(..) => "downloading",
+            Self::Booting(..) => "booting",
+            Self::Reported(..) => "reported",
+            Self::Finished(..) => "finished",
+            Self::Failed(..) => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
     pub const fn ip(&self) -> Option<IpAddr> {
         match self {
-            Self::Unassigned => None,
+            Self::Unassigned | Self::Cancelled => None,
 
             Self::Assigned(ip)
             | Self::Downloading(ip)
             | Self::Booting(ip)
             | Self::Reported(ip)
             | Self::Finished(ip)
-            | Self::Failed(ip) => Some(*ip),
+            | Self::Failed(ip, ..) => Some(*ip),
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// Parse a duration such as `90s`, `5m`, `2h` or `1d` (seconds by default)
+pub fn duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration: {s}"))?;
+
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("invalid duration unit: {unit}")),
+    };
+
+    Ok(Duration::from_secs(number.saturating_mul(seconds)))
+}
+
+/// Parse an asset timeout override such as `stress=2h`
+fn timeout(s: &str) -> Result<(String, Duration), String> {
+    let (filter, timeout) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected FILTER=DURATION: {s}"))?;
+
+    Ok((filter.to_string(), duration(timeout)?))
+}
+
+/// Parse an asset priority such as `stress=10`
+fn priority(s: &str) -> Result<(String, i32), String> {
+    let (filter, priority) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected FILTER=PRIORITY: {s}"))?;
+
+    let priority = priority
+        .parse()
+        .map_err(|_| format!("invalid priority: {priority}"))?;
+
+    Ok((filter.to_string(), priority))
+}
+
+/// A chain of stages that must run in order on the same host
+#[derive(Debug, Clone)]
+pub struct Chain(Vec<String>);
+
+/// Parse a chain of asset filters such as `flash,test,collect`
+fn chain(s: &str) -> Result<Chain, String> {
+    let stages: Vec<String> = s.split(',').map(|s| s.trim().to_string()).collect();
+    if stages.len() < 2 || stages.iter().any(String::is_empty) {
+        return Err(format!("expected at least two comma-separated stages: {s}"));
+    }
+
+    Ok(Chain(stages))
+}
+
+/// The order in which jobs are handed out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Schedule {
+    /// Highest priority first, then by asset name
+    #[default]
+    Priority,
+
+    /// Smallest asset first
+    ShortestFirst,
+
+    /// In random order
+    Random,
+}
+
+impl Schedule {
+    /// Pick the next job to hand out from the candidate indices
+    fn pick(self, jobs: &[Job], candidates: &[usize]) -> Option<usize> {
+        let iter = candidates.iter().copied();
+
+        match self {
+            Self::Priority => iter.min_by_key(|&i| std::cmp::Reverse(jobs[i].priority)),
+            Self::ShortestFirst => iter.min_by_key(|&i| jobs[i].asset.size),
+            Self::Random if candidates.is_empty() => None,
+            Self::Random => Some(candidates[rand::random_range(0..candidates.len())]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct JobsArgs {
+    /// Number of times to retry a failed job
+    #[arg(long, default_value_t = 0)]
+    pub retries: u32,
+
+    /// How long a job may stay assigned before it is returned to the queue
+    #[arg(long, value_name = "DURATION", default_value = "5m", value_parser = duration)]
+    pub assigned_timeout: Duration,
+
+    /// How long a job may stay downloading before it is returned to the queue
+    #[arg(long, value_name = "DURATION", default_value = "5m", value_parser = duration)]
+    pub downloading_timeout: Duration,
+
+    /// How long a job may run after booting before it fails
+    #[arg(long, value_name = "DURATION", value_parser = duration)]
+    pub booting_timeout: Option<Duration>,
+
+    /// How long a job may wait for its report to be filed before it fails
+    #[arg(long, value_name = "DURATION", value_parser = duration)]
+    pub reported_timeout: Option<Duration>,
+
+    /// Override the booting timeout of assets whose names contain FILTER
+    #[arg(long = "timeout", value_name = "FILTER=DURATION", value_parser = timeout)]
+    pub timeouts: Vec<(String, Duration)>,
+
+    /// File a GitHub issue when a job times out
+    #[arg(long)]
+    pub report_timeouts: bool,
+
+    /// Number of independent runs of each asset, each on a different host (default: 1)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub repeat: Option<u32>,
+
+    /// Run every asset on every host that requests a job
+    #[arg(long)]
+    pub each_host: bool,
+
+    /// JSON file of rules deciding which hosts may run which assets
+    #[arg(long, value_name = "FILE")]
+    pub rules: Option<PathBuf>,
+
+    /// Set the priority of assets whose names contain FILTER (default: 0)
+    #[arg(long = "priority", value_name = "FILTER=PRIORITY", value_parser = priority)]
+    pub priorities: Vec<(String, i32)>,
+
+    /// The order in which jobs are handed out
+    #[arg(long, value_enum, default_value_t)]
+    pub schedule: Schedule,
+
+    /// Run the assets whose names contain each FILTER in order on one host
+    #[arg(long = "chain", value_name = "FILTER,FILTER,...", value_parser = chain)]
+    pub chains: Vec<Chain>,
+}
+
+/// How long a job may stay in each state
+#[derive(Debug, Clone, Copy, Default)]
+struct Timeouts {
+    assigned: Duration,
+    downloading: Duration,
+    booting: Option<Duration>,
+    reported: Option<Duration>,
+}
+
+impl From<&JobsArgs> for Timeouts {
+    fn from(args: &JobsArgs) -> Self {
+        Self {
+            assigned: args.assigned_timeout,
+            downloading: args.downloading_timeout,
+            booting: args.booting_timeout,
+            reported: args.reported_timeout,
+        }
+    }
+}
+
+impl Timeouts {
+    /// The timeout of the job's current state (if any)
+    fn timeout(&self, job: &Job) -> Option<Duration> {
+        match job.state {
+            State::Assigned(..) => Some(self.assigned),
+            State::Downloading(..) => Some(self.downloading),
+            State::Booting(..) => job.timeout.or(self.booting),
+            State::Reported(..) => self.reported,
+            State::Unassigned | State::Finished(..) | State::Failed(..) | State::Cancelled => None,
+        }
+    }
+
+    fn overdue(&self, job: &Job) -> bool {
+        self.timeout(job)
+            .is_some_and(|timeout| job.elapsed() > timeout)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Job {
     pub asset: Asset,
+
+    /// Which run of the asset this job is (starting at 1)
+    pub run: u32,
+
+    /// The total number of runs of the asset
+    pub runs: u32,
+
+    /// The host this job must run on (if any)
+    pub host: Option<IpAddr>,
+
+    /// The host the operator pinned this job to, overriding `host`
+    pub pinned: Option<IpAddr>,
+
     pub state: State,
     pub seen: Option<SystemTime>,
+
+    /// The current attempt at running this job (starting at 1)
+    pub attempt: u32,
+
+    /// The maximum number of attempts at running this job
+    pub attempts: u32,
+
+    /// The IPs on which this job has failed
+    pub failed: BTreeSet<IpAddr>,
+
+    /// How long this job may run after booting, overriding the default
+    pub timeout: Option<Duration>,
+
+    /// Jobs with a higher priority are handed out first
+    pub priority: i32,
+
+    /// The source and name of the asset of the previous stage, if this job
+    /// is part of a chain
+    pub after: Option<(Option<Source>, String)>,
+
+    /// The hosts this job may run on (if not every host)
+    pub hosts: Vec<String>,
+
+    /// Labels to add to the issue filed for this job
+    pub labels: Vec<String>,
+
+    /// Users to assign to the issue filed for this job
+    pub assignees: Vec<String>,
+
+    /// Every state this job has been in, oldest first
+    pub history: Vec<Transition>,
+
+    /// The number of bytes of the asset served to hosts
+    pub downloaded: u64,
+
+    /// The title of the report filed for this job
+    pub report: Option<String>,
+
+    /// The body of the report filed for this job
+    pub body: Option<String>,
+
+    /// The URL of the GitHub issue filed for this job
+    pub issue: Option<String>,
+}
+
+/// A change in the state of a job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Transition {
+    pub state: State,
+    pub time: SystemTime,
 }
 
 impl Job {
+    /// Whether both are the same job, perhaps in different states
+    pub fn same(&self, other: &Self) -> bool {
+        self.asset.name == other.asset.name
+            && self.asset.source == other.asset.source
+            && self.run == other.run
+            && self.host == other.host
+    }
+
     fn elapsed(&self) -> Duration {
         self.seen
             .unwrap_or(SystemTime::UNIX_EPOCH)
             .elapsed()
             .unwrap_or_default()
     }
+
+    /// Mark the job as failed on an IP
+    ///
+    /// If the job has attempts remaining, it is returned to the queue
+    /// instead. `Jobs::assign()` will then prefer to give it to a different
+    /// IP than the ones it has already failed on.
+    fn fail(&mut self, ip: IpAddr, failure: Failure) {
+        self.failed.insert(ip);
+        self.enter(State::Failed(ip, failure));
+
+        if self.attempt < self.attempts {
+            self.attempt += 1;
+            self.enter(State::Unassigned);
+        }
+    }
+
+    /// Move the job into a new state, recording the transition
+    fn enter(&mut self, state: State) {
+        let time = SystemTime::now();
+
+        self.state = state;
+        self.seen = Some(time);
+        self.history.push(Transition { state, time });
+    }
 }
 
-pub struct Jobs(Vec<Job>);
+#[derive(Serialize, Deserialize)]
+pub struct Jobs {
+    queue: Vec<Job>,
+
+    /// The jobs that every host must run (in `--each-host` mode)
+    ///
+    /// These are copied into `queue`, pinned to the host, the first time
+    /// each host requests a job.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    each: Vec<Job>,
+
+    #[serde(skip)]
+    timeouts: Timeouts,
 
-impl From<BTreeSet<Asset>> for Jobs {
-    fn from(assets: BTreeSet<Asset>) -> Self {
-        Self(
-            assets
-                .into_iter()
-                .map(|asset| Job {
-                    asset,
+    #[serde(skip)]
+    rules: Rules,
+
+    #[serde(skip)]
+    schedule: Schedule,
+
+    #[serde(skip)]
+    paused: bool,
+
+    #[serde(skip)]
+    draining: bool,
+
+    /// The number of changes made to the jobs, to know when to save them
+    #[serde(skip)]
+    changes: usize,
+}
+
+impl Jobs {
+    /// Create the jobs for the assets of the releases
+    ///
+    /// Settings given on the command line take precedence over the settings
+    /// in the manifest of each release.
+    pub fn new(releases: &[Contents], args: &JobsArgs) -> Result<Self> {
+        Self::create(releases, args, &[])
+    }
+
+    /// Create the jobs for the assets of the releases, given the assets
+    /// that already have jobs
+    ///
+    /// A chain stage that matches none of the new assets is taken to be
+    /// the known asset it matches, so new stages can follow earlier ones.
+    fn create(releases: &[Contents], args: &JobsArgs, known: &[&Asset]) -> Result<Self> {
+        let stage = |asset: &Asset| (asset.source.clone(), asset.name.clone());
+
+        // Find the previous stage of every asset that is part of a chain.
+        let mut after = HashMap::new();
+        for Chain(stages) in &args.chains {
+            let mut previous = None;
+
+            for filter in stages {
+                let mut matching = releases
+                    .iter()
+                    .flat_map(|release| &release.assets)
+                    .filter(|a| a.name.contains(filter));
+                let Some(asset) = matching.next() else {
+                    let Some(asset) = known.iter().rev().find(|a| a.name.contains(filter)) else {
+                        anyhow::bail!("Chain stage `{filter}` matches no assets");
+                    };
+
+                    previous = Some(stage(asset));
+                    continue;
+                };
+
+                if matching.next().is_some() {
+                    anyhow::bail!("Chain stage `{filter}` matches more than one asset");
+                }
+
+                if after.insert(stage(asset), previous).is_some() {
+                    anyhow::bail!("Asset {} is in more than one chain stage", asset.name);
+                }
+
+                previous = Some(stage(asset));
+            }
+        }
+
+        let mut jobs = Vec::new();
+        for (mut asset, manifest) in releases.iter().flat_map(|release| {
+            release
+                .assets
+                .iter()
+                .map(|a| (a.clone(), &release.manifest))
+        }) {
+            let entry = manifest.entry(&asset).cloned().unwrap_or_default();
+
+            let timeout = args
+                .timeouts
+                .iter()
+                .find(|(filter, ..)| asset.name.contains(filter))
+                .map(|(.., timeout)| *timeout)
+                .or(entry.timeout);
+
+            let priority = args
+                .priorities
+                .iter()
+                .find(|(filter, ..)| asset.name.contains(filter))
+                .map(|(.., priority)| *priority)
+                .or(entry.priority)
+                .unwrap_or(0);
+
+            let runs = args.repeat.or(entry.repeat).unwrap_or(1);
+            let after = after.get(&stage(&asset)).cloned().flatten();
+
+            // Let the cache verify the asset against the manifest's digest.
+            if let Some(sha256) = entry.sha256 {
+                asset.digest = Some(format!("sha256:{sha256}"));
+            }
+
+            for run in 1..=runs {
+                jobs.push(Job {
+                    asset: asset.clone(),
+                    run,
+                    runs,
+                    host: None,
+                    pinned: None,
                     state: State::Unassigned,
                     seen: None,
+                    attempt: 1,
+                    attempts: args.retries.saturating_add(1),
+                    failed: BTreeSet::new(),
+                    timeout,
+                    priority,
+                    after: after.clone(),
+                    hosts: entry.hosts.clone(),
+                    labels: entry.labels.clone(),
+                    assignees: entry.assignees.clone(),
+                    history: Vec::new(),
+                    downloaded: 0,
+                    report: None,
+                    body: None,
+                    issue: None,
+                });
+            }
+        }
+
+        let (queue, each) = if args.each_host {
+            (Vec::new(), jobs)
+        } else {
+            (jobs, Vec::new())
+        };
+
+        let mut jobs = Self {
+            queue,
+            each,
+            timeouts: Timeouts::default(),
+            rules: Rules::default(),
+            schedule: Schedule::default(),
+            paused: false,
+            draining: false,
+            changes: 0,
+        };
+
+        jobs.configure(args)?;
+        Ok(jobs)
+    }
+
+    /// Add jobs for the assets of the releases that have none yet
+    ///
+    /// Returns the number of jobs added. In `--each-host` mode, the hosts
+    /// that already have their jobs also get the new ones.
+    pub fn extend(&mut self, releases: &[Contents], args: &JobsArgs) -> Result<usize> {
+        let known = self
+            .queue
+            .iter()
+            .chain(&self.each)
+            .map(|job| &job.asset)
+            .collect::<Vec<_>>();
+
+        let fresh = releases
+            .iter()
+            .map(|release| Contents {
+                source: release.source.clone(),
+                assets: release
+                    .assets
+                    .iter()
+                    .filter(|a| {
+                        !known
+                            .iter()
+                            .any(|k| k.name == a.name && k.source == a.source)
+                    })
+                    .cloned()
+                    .collect(),
+                manifest: release.manifest.clone(),
+                warnings: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+
+        if fresh.iter().all(|release| release.assets.is_empty()) {
+            return Ok(0);
+        }
+
+        let added = Self::create(&fresh, args, &known)?;
+        let hosts = self
+            .queue
+            .iter()
+            .filter_map(|job| job.host)
+            .collect::<BTreeSet<_>>();
+
+        for ip in hosts {
+            let host = Host::new(ip);
+            let jobs = added
+                .each
+                .iter()
+                .filter(|job| {
+                    self.rules.allows(&host, &job.asset.name)
+                        && self.rules.admits(&host, &job.hosts)
                 })
-                .collect(),
-        )
+                .map(|job| Job {
+                    host: Some(ip),
+                    ..job.clone()
+                })
+                .collect::<Vec<_>>();
+
+            self.queue.extend(jobs);
+        }
+
+        let count = added.queue.len() + added.each.len();
+        self.changes += 1;
+        self.queue.extend(added.queue);
+        self.each.extend(added.each);
+        Ok(count)
     }
-}
 
-impl Jobs {
-    const TIMEOUT: Duration = Duration::from_mins(5);
+    /// Apply the settings that aren't saved in the state file
+    fn configure(&mut self, args: &JobsArgs) -> Result<()> {
+        self.timeouts = args.into();
+        self.schedule = args.schedule;
+        self.rules = args
+            .rules
+            .as_deref()
+            .map(Rules::load)
+            .transpose()?
+            .unwrap_or_default();
+
+        for job in self.queue.iter().chain(&self.each) {
+            self.rules
+                .check(&job.hosts)
+                .with_context(|| format!("Invalid hosts for {}", job.asset.name))?;
+        }
+
+        Ok(())
+    }
 
     pub fn iter(&self) -> impl Iterator<Item = &Job> {
-        self.0.iter()
+        self.queue.iter()
+    }
+
+    /// All of the distinct assets that jobs may run
+    pub fn assets(&self) -> BTreeSet<&Asset> {
+        self.queue
+            .iter()
+            .chain(&self.each)
+            .map(|job| &job.asset)
+            .collect()
+    }
+
+    /// Whether the IP has already run (or is running) another job for an asset
+    fn ran(&self, job: &Job, ip: IpAddr) -> bool {
+        self.queue.iter().any(|other| {
+            other.asset == job.asset
+                && other.run != job.run
+                && (other.state.ip() == Some(ip) || other.failed.contains(&ip))
+        })
+    }
+
+    /// The job for the previous stage of a chain
+    fn before(&self, job: &Job) -> Option<&Job> {
+        let (source, name) = job.after.as_ref()?;
+        self.queue.iter().find(|other| {
+            other.asset.name == *name
+                && other.asset.source == *source
+                && other.run == job.run
+                && other.host == job.host
+        })
+    }
+
+    /// Whether the job may be given to the IP
+    ///
+    /// A later stage of a chain may only be given to the IP that finished
+    /// the previous stage.
+    fn eligible(&self, job: &Job, host: &Host) -> bool {
+        let ip = host.ip();
+
+        self.rules.allows(host, &job.asset.name)
+            && self.rules.admits(host, &job.hosts)
+            && job
+                .pinned
+                .or(job.host)
+                .map_or_else(|| !self.ran(job, ip), |host| host == ip)
+            && self
+                .before(job)
+                .is_none_or(|before| before.state == State::Finished(ip))
+    }
+
+    /// Cancel the later stages of chains whose earlier stages failed
+    fn cascade(&mut self) {
+        loop {
+            let doomed = (0..self.queue.len())
+                .filter(|&i| {
+                    self.queue[i].state == State::Unassigned
+                        && self.before(&self.queue[i]).is_some_and(|before| {
+                            matches!(before.state, State::Failed(..) | State::Cancelled)
+                        })
+                })
+                .collect::<Vec<_>>();
+
+            if doomed.is_empty() {
+                break;
+            }
+
+            for i in doomed {
+                self.queue[i].enter(State::Cancelled);
+                self.changes += 1;
+            }
+        }
+    }
+
+    /// Load the jobs from a state file written by `Status::save()`
+    pub fn load(path: &Path, args: &JobsArgs) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open state file {}", path.display()))?;
+
+        let mut jobs: Self = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Failed to parse state file {}", path.display()))?;
+
+        // Reported jobs are finished a few seconds after their issue is filed,
+        // which won't happen now. Finish the ones whose issue was filed, and
+        // give the others another attempt.
+        for job in &mut jobs.queue {
+            if let State::Reported(ip) = job.state {
+                if job.issue.is_some() {
+                    job.enter(State::Finished(ip));
+                } else {
+                    job.attempts = job.attempts.max(job.attempt + 1);
+                    job.attempt += 1;
+                    job.enter(State::Unassigned);
+                }
+            }
+        }
+
+        jobs.configure(args)?;
+        Ok(jobs)
     }
 
     pub fn assign(&mut self, ip: IpAddr) -> Option<Asset> {
+        let host = Host::new(ip);
+
+        // Give hosts we haven't seen before their own copy of every job.
+        if !self.each.is_empty() && !self.queue.iter().any(|job| job.host == Some(ip)) {
+            let jobs = self
+                .each
+                .iter()
+                .filter(|job| {
+                    self.rules.allows(&host, &job.asset.name)
+                        && self.rules.admits(&host, &job.hosts)
+                })
+                .map(|job| Job {
+                    host: Some(ip),
+                    ..job.clone()
+                })
+                .collect::<Vec<_>>();
+
+            self.queue.extend(jobs);
+            self.changes += 1;
+        }
+
         // First, try to find a job that is already assigned to this IP.
-        for job in &mut self.0 {
+        for job in &mut self.queue {
             match job.state {
                 State::Assigned(addr) if addr == ip => {
-                    job.state = State::Assigned(ip);
                     job.seen = Some(SystemTime::now());
                     return Some(job.asset.clone());
                 }
@@ -84,21 +750,64 @@ impl Jobs {
             }
         }
 
-        // Next, try to find an unassigned or expired job.
-        for job in &mut self.0 {
-            match job.state {
-                State::Unassigned => {
-                    job.state = State::Assigned(ip);
-                    job.seen = Some(SystemTime::now());
-                    return Some(job.asset.clone());
-                }
+        // No new jobs are handed out while paused or draining.
+        if self.paused || self.draining {
+            return None;
+        }
+
+        // Next, try to find an unassigned or expired job. Prefer jobs that
+        // haven't already failed on this IP.
+        let timeouts = self.timeouts;
+        let ready = |job: &Job| match job.state {
+            State::Unassigned => true,
+            State::Assigned(..) | State::Downloading(..) => timeouts.overdue(job),
+            _ => false,
+        };
+
+        let candidates = (0..self.queue.len())
+            .filter(|&i| ready(&self.queue[i]) && self.eligible(&self.queue[i], &host))
+            .collect::<Vec<_>>();
+
+        let fresh = candidates
+            .iter()
+            .copied()
+            .filter(|&i| !self.queue[i].failed.contains(&ip))
+            .collect::<Vec<_>>();
+
+        // Keep a host on its chain by preferring the next stage of a chain
+        // it has already started.
+        let chained = fresh
+            .iter()
+            .copied()
+            .filter(|&i| self.queue[i].after.is_some())
+            .collect::<Vec<_>>();
+
+        let preferred = if !chained.is_empty() {
+            &chained
+        } else if !fresh.is_empty() {
+            &fresh
+        } else {
+            &candidates
+        };
+        let index = self.schedule.pick(&self.queue, preferred)?;
 
-                State::Assigned(..) | State::Downloading(..) if job.elapsed() > Self::TIMEOUT => {
-                    job.state = State::Assigned(ip);
+        let job = &mut self.queue[index];
+        job.enter(State::Assigned(ip));
+        self.changes += 1;
+        Some(job.asset.clone())
+    }
+
+    /// Find the asset that this IP was part-way through downloading
+    ///
+    /// This allows a client to resume an interrupted download of its asset
+    /// with a ranged request rather than being handed a different job.
+    pub fn resume(&mut self, ip: IpAddr) -> Option<Asset> {
+        for job in &mut self.queue {
+            match job.state {
+                State::Downloading(addr) if addr == ip => {
                     job.seen = Some(SystemTime::now());
                     return Some(job.asset.clone());
                 }
-
                 _ => {}
             }
         }
@@ -107,11 +816,11 @@ impl Jobs {
     }
 
     pub fn downloading(&mut self, ip: IpAddr) -> Option<&Asset> {
-        for job in &mut self.0 {
+        for job in &mut self.queue {
             match job.state {
                 State::Assigned(addr) if addr == ip => {
-                    job.state = State::Downloading(ip);
-                    job.seen = Some(SystemTime::now());
+                    job.enter(State::Downloading(ip));
+                    self.changes += 1;
                     return Some(&job.asset);
                 }
                 _ => {}
@@ -122,11 +831,11 @@ impl Jobs {
     }
 
     pub fn booting(&mut self, ip: IpAddr) -> bool {
-        for job in &mut self.0 {
+        for job in &mut self.queue {
             match job.state {
                 State::Downloading(addr) if addr == ip => {
-                    job.state = State::Booting(ip);
-                    job.seen = Some(SystemTime::now());
+                    job.enter(State::Booting(ip));
+                    self.changes += 1;
                     return true;
                 }
                 _ => {}
@@ -136,34 +845,320 @@ impl Jobs {
         false
     }
 
-    pub fn report(&mut self, ip: IpAddr) -> bool {
-        for job in &mut self.0 {
+    /// Mark the job booting on this IP as reported, returning the job
+    pub fn report(&mut self, ip: IpAddr, report: &Report) -> Option<&Job> {
+        for job in &mut self.queue {
             match job.state {
                 State::Booting(addr) if addr == ip => {
-                    job.state = State::Reported(ip);
-                    job.seen = Some(SystemTime::now());
-                    return true;
+                    job.enter(State::Reported(ip));
+                    job.report = Some(report.title().to_string());
+                    job.body = report.body().map(str::to_string);
+                    self.changes += 1;
+                    return Some(job);
                 }
                 _ => {}
             }
         }
 
-        false
+        None
+    }
+
+    /// Record the URL of the issue filed for the job reported by this IP
+    pub fn filed(&mut self, ip: IpAddr, url: String) {
+        if let Some(job) = self
+            .queue
+            .iter_mut()
+            .find(|job| job.state == State::Reported(ip))
+        {
+            job.issue = Some(url);
+            self.changes += 1;
+        }
+    }
+
+    /// Count bytes of an asset served to this IP against the job it holds
+    pub fn downloaded(&mut self, ip: IpAddr, bytes: u64) {
+        let held = |job: &&mut Job| match job.state {
+            State::Assigned(addr)
+            | State::Downloading(addr)
+            | State::Booting(addr)
+            | State::Reported(addr) => addr == ip,
+            _ => false,
+        };
+
+        if let Some(job) = self.queue.iter_mut().find(held) {
+            job.downloaded += bytes;
+            self.changes += 1;
+        }
     }
 
     pub fn finish(&mut self, ip: IpAddr) -> bool {
-        for job in &mut self.0 {
-            job.state = match job.state {
-                State::Assigned(addr) if addr == ip => State::Failed(ip),
-                State::Booting(addr) if addr == ip => State::Failed(ip),
-                State::Reported(addr) if addr == ip => State::Finished(ip),
-                _ => continue,
-            };
+        let Some(job) = self.queue.iter_mut().find(|job| match job.state {
+            State::Assigned(addr) | State::Booting(addr) | State::Reported(addr) => addr == ip,
+            _ => false,
+        }) else {
+            return false;
+        };
 
-            job.seen = Some(SystemTime::now());
-            return true;
+        match job.state {
+            State::Assigned(..) => job.fail(ip, Failure::NotBooted),
+            State::Booting(..) => job.fail(ip, Failure::NotReported),
+            _ => {
+                job.enter(State::Finished(ip));
+            }
+        }
+
+        self.changes += 1;
+        self.cascade();
+        true
+    }
+
+    /// Fail the job whose report from this IP could not be filed
+    pub fn unreported(&mut self, ip: IpAddr) -> bool {
+        for job in &mut self.queue {
+            match job.state {
+                State::Reported(addr) if addr == ip => {
+                    job.fail(ip, Failure::ReportFailed);
+                    self.changes += 1;
+                    self.cascade();
+                    return true;
+                }
+                _ => {}
+            }
         }
 
         false
     }
+
+    /// Handle jobs that have been in their current state for too long
+    ///
+    /// Jobs that never got as far as booting are returned to the queue.
+    /// Jobs that booted but did not finish in time are failed. The failed
+    /// jobs are returned as they were before they timed out.
+    pub fn reap(&mut self) -> Vec<Job> {
+        let mut reaped = Vec::new();
+
+        for job in &mut self.queue {
+            if !self.timeouts.overdue(job) {
+                continue;
+            }
+
+            match job.state {
+                State::Assigned(..) | State::Downloading(..) => {
+                    job.enter(State::Unassigned);
+                    self.changes += 1;
+                }
+
+                State::Booting(ip) | State::Reported(ip) => {
+                    reaped.push(job.clone());
+                    job.fail(ip, Failure::Timeout);
+                    self.changes += 1;
+                }
+
+                State::Unassigned | State::Finished(..) | State::Failed(..) | State::Cancelled => {}
+            }
+        }
+
+        self.cascade();
+        reaped
+    }
+
+    /// The timeout of the job's current state (if any)
+    pub fn timeout(&self, job: &Job) -> Option<Duration> {
+        self.timeouts.timeout(job)
+    }
+
+    /// Whether any job runs the named asset
+    pub fn contains(&self, name: &str) -> bool {
+        self.queue.iter().any(|job| job.asset.name == name)
+    }
+
+    /// Return the matching failed, cancelled or stuck jobs to the queue
+    ///
+    /// A failed job is given one more attempt. Returns the number of jobs
+    /// that were requeued.
+    pub fn requeue(&mut self, matches: impl Fn(&Job) -> bool) -> usize {
+        let mut requeued = 0;
+
+        for job in self.queue.iter_mut().filter(|job| matches(job)) {
+            match job.state {
+                State::Failed(..) => {
+                    job.attempts = job.attempts.max(job.attempt + 1);
+                    job.attempt += 1;
+                }
+
+                State::Cancelled
+                | State::Assigned(..)
+                | State::Downloading(..)
+                | State::Booting(..) => {}
+
+                State::Unassigned | State::Reported(..) | State::Finished(..) => continue,
+            }
+
+            job.enter(State::Unassigned);
+            requeued += 1;
+        }
+
+        self.changes += requeued;
+        requeued
+    }
+
+    /// Cancel the matching unfinished jobs
+    ///
+    /// Returns the number of jobs that were cancelled.
+    pub fn cancel(&mut self, matches: impl Fn(&Job) -> bool) -> usize {
+        let mut cancelled = 0;
+
+        for job in self.queue.iter_mut().filter(|job| matches(job)) {
+            match job.state {
+                State::Unassigned
+                | State::Assigned(..)
+                | State::Downloading(..)
+                | State::Booting(..) => {
+                    job.enter(State::Cancelled);
+                    cancelled += 1;
+                }
+
+                State::Reported(..)
+                | State::Finished(..)
+                | State::Failed(..)
+                | State::Cancelled => {}
+            }
+        }
+
+        self.changes += cancelled;
+        self.cascade();
+        cancelled
+    }
+
+    /// Fail the matching jobs that are running on a host
+    ///
+    /// As with any other failure, jobs with attempts remaining are returned
+    /// to the queue. Returns the number of jobs that were failed.
+    pub fn fail(&mut self, matches: impl Fn(&Job) -> bool) -> usize {
+        let mut failed = 0;
+
+        for job in self.queue.iter_mut().filter(|job| matches(job)) {
+            match job.state {
+                State::Assigned(ip)
+                | State::Downloading(ip)
+                | State::Booting(ip)
+                | State::Reported(ip) => {
+                    job.fail(ip, Failure::Operator);
+                    failed += 1;
+                }
+
+                State::Unassigned | State::Finished(..) | State::Failed(..) | State::Cancelled => {}
+            }
+        }
+
+        self.changes += failed;
+        self.cascade();
+        failed
+    }
+
+    /// Pin the matching unfinished jobs to a host
+    ///
+    /// Jobs that another host has been given but hasn't booted yet are
+    /// returned to the queue. Returns the number of jobs that were pinned.
+    pub fn pin(&mut self, matches: impl Fn(&Job) -> bool, ip: IpAddr) -> usize {
+        let mut pinned = 0;
+
+        for job in self.queue.iter_mut().filter(|job| matches(job)) {
+            match job.state {
+                State::Assigned(addr) | State::Downloading(addr) if addr != ip => {
+                    job.enter(State::Unassigned);
+                }
+
+                State::Reported(..)
+                | State::Finished(..)
+                | State::Failed(..)
+                | State::Cancelled => continue,
+
+                _ => {}
+            }
+
+            job.pinned = Some(ip);
+            pinned += 1;
+        }
+
+        self.changes += pinned;
+        pinned
+    }
+
+    /// Return the jobs held by an IP to the queue without failing them
+    ///
+    /// Returns the number of jobs that were released.
+    pub fn release(&mut self, ip: IpAddr) -> usize {
+        let mut released = 0;
+
+        for job in &mut self.queue {
+            match job.state {
+                State::Assigned(addr) | State::Downloading(addr) | State::Booting(addr)
+                    if addr == ip =>
+                {
+                    job.enter(State::Unassigned);
+                    released += 1;
+                }
+
+                _ => {}
+            }
+        }
+
+        self.changes += released;
+        released
+    }
+
+    /// The number of changes made to the jobs so far
+    pub const fn changes(&self) -> usize {
+        self.changes
+    }
+
+    /// Stop (or resume) handing out new jobs
+    pub const fn pause(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Stop handing out new jobs for good, letting running jobs finish
+    pub const fn drain(&mut self) {
+        self.draining = true;
+    }
+
+    pub const fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub const fn draining(&self) -> bool {
+        self.draining
+    }
+
+    /// Whether every job has reached a final state
+    ///
+    /// In `--each-host` mode, this waits for at least one host to take its jobs.
+    pub fn done(&self) -> bool {
+        (self.each.is_empty() || !self.queue.is_empty())
+            && self.queue.iter().all(|job| {
+                matches!(
+                    job.state,
+                    State::Finished(..) | State::Failed(..) | State::Cancelled
+                )
+            })
+    }
+
+    /// Whether every job has finished
+    pub fn complete(&self) -> bool {
+        self.queue
+            .iter()
+            .all(|job| matches!(job.state, State::Finished(..)))
+    }
+
+    /// Whether draining has finished, because no jobs are running
+    pub fn drained(&self) -> bool {
+        self.draining
+            && self.queue.iter().all(|job| {
+                matches!(
+                    job.state,
+                    State::Unassigned | State::Finished(..) | State::Failed(..) | State::Cancelled
+                )
+            })
+    }
 }
@@ -12,15 +12,24 @@
 
 mod avahi;
 mod github;
+mod glob;
 mod http;
 mod jobs;
+mod manifest;
+mod rules;
+mod summary;
 mod tui;
 
+use std::path::PathBuf;
+use std::process::ExitCode;
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::avahi::AvahiService;
-use crate::github::GitHubArgs;
-use crate::http::Server;
+use crate::github::{GitHub, GitHubArgs, Report};
+use crate::http::{Server, Webhook};
+use crate::jobs::{Jobs, JobsArgs};
+use crate::summary::{Format, Summary};
 use crate::tui::{Status, Throbbing};
 
 use anyhow::Result;
@@ -28,6 +37,7 @@ use clap::Parser;
 use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind};
 use futures_util::StreamExt;
 use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::Mutex;
 
 #[derive(Parser)]
@@ -37,6 +47,9 @@ struct Args {
     #[command(flatten)]
     github: GitHubArgs,
 
+    #[command(flatten)]
+    jobs: JobsArgs,
+
     /// Address and port to bind to
     #[arg(short = 'b', long, default_value = "0.0.0.0:8080")]
     bind: String,
@@ -44,6 +57,71 @@ struct Args {
     /// Path to offer services on
     #[arg(short = 'p', long, default_value = concat!("/", std::env!("CARGO_PKG_NAME")))]
     path: String,
+
+    /// File to save the job state to after every change
+    #[arg(short = 's', long, value_name = "FILE", conflicts_with = "resume")]
+    state: Option<PathBuf>,
+
+    /// Resume an interrupted run from its state file
+    #[arg(long, value_name = "FILE")]
+    resume: Option<PathBuf>,
+
+    /// Directory to cache downloaded assets in
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Download all assets into the cache at startup
+    #[arg(long, requires = "cache_dir")]
+    prefetch: bool,
+
+    /// Extra domain that asset downloads may be redirected to
+    #[arg(long = "redirect-domain", value_name = "DOMAIN")]
+    redirect_domains: Vec<String>,
+
+    /// Bearer token required by the administrative API (disabled if unset)
+    #[arg(long, env = "DISPATCH_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
+
+    /// Secret that GitHub webhook deliveries are signed with (disabled if unset)
+    #[arg(long, env = "DISPATCH_WEBHOOK_SECRET", hide_env_values = true)]
+    webhook_secret: Option<String>,
+
+    /// Run without the terminal UI, logging events to standard output
+    #[arg(long)]
+    headless: bool,
+
+    /// Exit once every job has finished, failed or been cancelled
+    #[arg(long)]
+    exit_when_done: bool,
+
+    /// How long to keep serving poweroff after the last job is done
+    #[arg(long, value_name = "DURATION", default_value = "30s", value_parser = jobs::duration)]
+    exit_grace: Duration,
+
+    /// Check the releases for new assets every INTERVAL, adding their jobs
+    #[arg(
+        long,
+        value_name = "INTERVAL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "5m",
+        value_parser = interval
+    )]
+    watch: Option<Duration>,
+
+    /// Keep the run summary up to date in FILE (.json, .md or .xml)
+    #[arg(long = "summary", value_name = "FILE", value_parser = summary::file)]
+    summaries: Vec<(PathBuf, Format)>,
+}
+
+/// Parse a non-zero interval such as `5m`
+fn interval(s: &str) -> Result<Duration, String> {
+    let interval = jobs::duration(s)?;
+    if interval.is_zero() {
+        return Err(format!("interval must not be zero: {s}"));
+    }
+
+    Ok(interval)
 }
 
 /// Guard that ensures term settings are restored upon program exit
@@ -56,13 +134,16 @@ impl Drop for CleanupGuard {
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     // Create cleanup guard - will automatically reset term settings
     // on program exit, even on panic and error
     let _cleanup = CleanupGuard;
 
     // Parse arguments
     let args = Args::parse();
+    if args.headless {
+        tui::headless();
+    }
 
     // Ensure we're authenticated with GitHub
     let github = Arc::new(args.github.login().await?);
@@ -72,18 +153,57 @@ async fn main() -> Result<()> {
     let addr = listener.local_addr()?;
     let path = Arc::new(args.path);
 
-    // Load the github assets
-    let assets = github
-        .assets()
-        .throbbing("Loading GitHub assets...")
-        .await?;
+    // Load the jobs, either from a previous run or from the GitHub assets
+    let mut warnings = Vec::new();
+    let jobs = if let Some(path) = &args.resume {
+        Jobs::load(path, &args.jobs)?
+    } else {
+        let mut releases = github
+            .assets()
+            .throbbing("Loading GitHub assets...")
+            .await?;
+
+        for release in &mut releases {
+            tui::log("release", &[("source", &release.source)]);
+            warnings.append(&mut release.warnings);
+        }
+
+        Jobs::new(&releases, &args.jobs)?
+    };
 
     // Show the main UI
-    let status = Arc::new(Mutex::new(Status::new(assets, addr, path.clone())));
+    let state = args.resume.or(args.state);
+    let summaries = args.summaries;
+    let mut status = Status::new(jobs, addr, path.clone(), state, summaries);
+    for warning in warnings {
+        status.warn(warning);
+    }
+
+    let status = Arc::new(Mutex::new(status));
+    status.lock().await.save()?;
     status.lock().await.render()?;
+    tui::log("listening", &[("url", &format!("http://{addr}{path}"))]);
 
     // Create the HTTP server
-    let server = Server::new(listener, status.clone(), github, path.clone())?;
+    let webhook = args
+        .webhook_secret
+        .map(|secret| Webhook::new(secret, args.jobs.clone()));
+
+    let server = Server::new(
+        listener,
+        status.clone(),
+        github.clone(),
+        path.clone(),
+        args.cache_dir,
+        args.admin_token,
+        webhook,
+        args.redirect_domains,
+    )?;
+
+    // Warm up the asset cache
+    if args.prefetch {
+        server.prefetch().await;
+    }
 
     // Create TXT records
     let name = std::env!("CARGO_PKG_NAME");
@@ -97,13 +217,57 @@ async fn main() -> Result<()> {
     let avahi = AvahiService::new().await?;
     avahi.register(name, addr.port(), &txt).await?;
 
-    // Create event stream for terminal events
-    let mut events = EventStream::new();
+    // Handle terminal events, unless there is no terminal
+    let events = async {
+        if args.headless {
+            std::future::pending().await
+        } else {
+            terminal_events(&mut EventStream::new(), status.clone()).await
+        }
+    };
+
+    // The watch future is made even if it is disabled, but is never polled.
+    let interval = args.watch.unwrap_or_default();
 
     // Run the server and wait for quit or terminal events in parallel
     tokio::select! {
         _ = server.serve() => {}
-        _ = terminal_events(&mut events, status.clone()) => {}
+        _ = events => {}
+        _ = signalled() => {}
+        () = reaper(status.clone(), github.clone(), args.jobs.report_timeouts) => {}
+        () = watch(status.clone(), github, &args.jobs, interval), if args.watch.is_some() => {}
+        () = drained(status.clone()) => {}
+        () = done(status.clone(), args.exit_grace), if args.exit_when_done => {}
+    }
+
+    // Summarize the run once the terminal has been restored.
+    let (summary, complete) = {
+        let mut status = status.lock().await;
+        status.save()?;
+        (Summary::from(status.jobs()), status.jobs().complete())
+    };
+
+    tui::cleanup();
+    if args.exit_when_done || args.headless {
+        print!("{}", summary.table());
+    }
+
+    // When exiting unattended, the exit code tells whether every job finished.
+    tui::log("exit", &[("complete", &complete)]);
+    Ok(if (args.headless || args.exit_when_done) && !complete {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Wait for SIGTERM or SIGINT
+async fn signalled() -> Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    tokio::select! {
+        _ = terminate.recv() => {}
+        result = tokio::signal::ctrl_c() => result?,
     }
 
     Ok(())
@@ -113,12 +277,19 @@ async fn terminal_events(events: &mut EventStream, status: Arc<Mutex<Status>>) -
     loop {
         if let Some(event) = events.next().await {
             match event? {
-                // Quit on 'q' key press
+                // Quit on 'q' key press, unless answering a prompt
                 Event::Key(KeyEvent {
-                    code: KeyCode::Char('q'),
+                    code,
                     kind: KeyEventKind::Press,
                     ..
-                }) => break,
+                }) => {
+                    let mut status = status.lock().await;
+                    if code == KeyCode::Char('q') && !status.prompting() {
+                        break;
+                    }
+
+                    status.key(code)?;
+                }
 
                 // Re-render the status on terminal resize
                 Event::Resize(_, _) => status.lock().await.render()?,
@@ -131,3 +302,99 @@ async fn terminal_events(events: &mut EventStream, status: Arc<Mutex<Status>>) -
 
     Ok(())
 }
+
+/// Wait until every job is done, and has stayed done for the grace period
+async fn done(status: Arc<Mutex<Status>>, grace: Duration) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        interval.tick().await;
+
+        if status.lock().await.jobs().done() {
+            tokio::time::sleep(grace).await;
+
+            if status.lock().await.jobs().done() {
+                break;
+            }
+        }
+    }
+}
+
+/// Wait until draining has finished
+async fn drained(status: Arc<Mutex<Status>>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        interval.tick().await;
+
+        if status.lock().await.jobs().drained() {
+            break;
+        }
+    }
+}
+
+/// Periodically check the releases for new assets and add jobs for them
+async fn watch(
+    status: Arc<Mutex<Status>>,
+    github: Arc<GitHub>,
+    args: &JobsArgs,
+    interval: Duration,
+) {
+    let mut interval = tokio::time::interval(interval);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        let releases = match github.assets().await {
+            Ok(releases) => releases,
+            Err(error) => {
+                let warning = format!("Failed to check for new assets: {error:#}");
+                status.lock().await.warn(warning);
+                continue;
+            }
+        };
+
+        status.lock().await.add(&releases, args);
+    }
+}
+
+/// Periodically fail or requeue jobs that have timed out
+async fn reaper(status: Arc<Mutex<Status>>, github: Arc<GitHub>, report: bool) {
+    let mut interval = tokio::time::interval(Duration::from_secs(10));
+
+    loop {
+        interval.tick().await;
+
+        let reaped = status.lock().await.update().reap();
+        if !report {
+            continue;
+        }
+
+        for job in reaped {
+            let ip = job.state.ip().map_or_else(String::new, |ip| ip.to_string());
+            let timeout = status.lock().await.jobs().timeout(&job).unwrap_or_default();
+
+            // Jobs with attempts remaining were returned to the queue.
+            let outcome = if job.attempt < job.attempts {
+                "returned to the queue for another attempt"
+            } else {
+                "failed"
+            };
+
+            let title = format!("{} timed out on {ip}", job.asset.name);
+            let body = format!(
+                "Job `{}` was in state `{}` on {ip} for more than {} seconds and was {outcome}.",
+                job.asset.name,
+                job.state.name(),
+                timeout.as_secs(),
+            );
+
+            let mut report = Report::new(title, body);
+            report.tag(&job.labels, &job.assignees);
+
+            let source = job.asset.source.as_ref();
+            let _ = github.report(source, report).await;
+        }
+    }
+}
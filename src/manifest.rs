@@ -0,0 +1,175 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::github::Asset;
+use crate::glob;
+use crate::jobs::duration;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawEntry {
+    assets: Vec<String>,
+
+    #[serde(default)]
+    timeout: Option<String>,
+
+    #[serde(default)]
+    priority: Option<i32>,
+
+    #[serde(default)]
+    repeat: Option<u32>,
+
+    #[serde(default)]
+    hosts: Vec<String>,
+
+    #[serde(default)]
+    labels: Vec<String>,
+
+    #[serde(default)]
+    assignees: Vec<String>,
+
+    #[serde(default)]
+    sha256: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawManifest {
+    #[serde(default)]
+    jobs: Vec<RawEntry>,
+}
+
+/// The dispatch settings for the assets matching a manifest entry
+#[derive(Debug, Clone, Default)]
+pub struct Entry {
+    patterns: Vec<String>,
+
+    /// How long the job may run after booting
+    pub timeout: Option<Duration>,
+
+    /// Jobs with a higher priority are handed out first
+    pub priority: Option<i32>,
+
+    /// The number of independent runs of the asset
+    pub repeat: Option<u32>,
+
+    /// The hosts that may run the asset (if not every host)
+    pub hosts: Vec<String>,
+
+    /// Labels to add to the issues filed for the asset
+    pub labels: Vec<String>,
+
+    /// Users to assign to the issues filed for the asset
+    pub assignees: Vec<String>,
+
+    /// The expected hex-encoded SHA-256 digest of the asset
+    pub sha256: Option<String>,
+}
+
+impl Entry {
+    fn matches(&self, asset: &Asset) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| glob::matches(pattern, &asset.name))
+    }
+
+    fn validate(
+        &mut self,
+        timeout: Option<String>,
+        sha256: Option<String>,
+        assets: &[&Asset],
+    ) -> Result<()> {
+        if assets.is_empty() {
+            anyhow::bail!("The entry matches no assets");
+        }
+
+        if let Some(timeout) = timeout {
+            self.timeout = Some(duration(&timeout).map_err(anyhow::Error::msg)?);
+        }
+
+        if self.repeat == Some(0) {
+            anyhow::bail!("The repeat count must be at least 1");
+        }
+
+        if let Some(sha256) = sha256 {
+            let sha256 = sha256.to_ascii_lowercase();
+            if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+                anyhow::bail!("Invalid sha256 digest: {sha256}");
+            }
+
+            if assets.len() > 1 {
+                anyhow::bail!("A sha256 digest may only apply to a single asset");
+            }
+
+            if let Some(digest) = assets[0].sha256() {
+                if !digest.eq_ignore_ascii_case(&sha256) {
+                    anyhow::bail!("The sha256 digest doesn't match GitHub's digest {digest}");
+                }
+            }
+
+            self.sha256 = Some(sha256);
+        }
+
+        Ok(())
+    }
+}
+
+/// Per-asset dispatch settings published alongside the assets of a release
+///
+/// The manifest is an optional release asset named `dispatch.json`. Each
+/// entry applies to the assets whose names match one of its patterns. The
+/// first entry matching an asset applies to it.
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    entries: Vec<Entry>,
+}
+
+impl Manifest {
+    /// The name of the release asset holding the manifest
+    pub const NAME: &str = "dispatch.json";
+
+    /// Parse and validate a manifest against the assets of its release
+    pub fn parse(bytes: &[u8], assets: &[Asset]) -> Result<Self> {
+        let raw: RawManifest = serde_json::from_slice(bytes)
+            .with_context(|| format!("Failed to parse {}", Self::NAME))?;
+
+        let mut entries = Vec::new();
+        for raw in raw.jobs {
+            let mut entry = Entry {
+                patterns: raw.assets,
+                timeout: None,
+                priority: raw.priority,
+                repeat: raw.repeat,
+                hosts: raw.hosts,
+                labels: raw.labels,
+                assignees: raw.assignees,
+                sha256: None,
+            };
+
+            let matching: Vec<&Asset> = assets.iter().filter(|a| entry.matches(a)).collect();
+            let names = matching
+                .iter()
+                .map(|asset| asset.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            entry
+                .validate(raw.timeout, raw.sha256, &matching)
+                .with_context(|| match names.as_str() {
+                    "" => format!("Invalid {} entry for {:?}", Self::NAME, entry.patterns),
+                    names => format!("Invalid {} entry for {names}", Self::NAME),
+                })?;
+
+            entries.push(entry);
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// The entry applying to an asset (if any)
+    pub fn entry(&self, asset: &Asset) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.matches(asset))
+    }
+}
@@ -0,0 +1,301 @@
+use std::cell::{OnceCell, RefCell};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::IpAddr;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::glob;
+
+/// A hardware (MAC) address
+type Mac = [u8; 6];
+
+fn parse_mac(s: &str) -> Option<Mac> {
+    let mut mac = [0; 6];
+    let mut parts = s.split([':', '-']);
+
+    for byte in &mut mac {
+        *byte = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+
+    parts.next().is_none().then_some(mac)
+}
+
+/// Look up the MAC address of an IP in the kernel's ARP table
+fn arp(ip: IpAddr) -> Option<Mac> {
+    let table = std::fs::read_to_string("/proc/net/arp").ok()?;
+
+    // IP address  HW type  Flags  HW address  Mask  Device
+    table.lines().skip(1).find_map(|line| {
+        let mut columns = line.split_whitespace();
+        let addr: IpAddr = columns.next()?.parse().ok()?;
+        (addr == ip).then(|| parse_mac(columns.nth(2)?))?
+    })
+}
+
+/// A host selector: `*`, an IP, a CIDR network, a MAC address or a label
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Selector {
+    Any,
+    Net(IpAddr, u8),
+    Mac(Mac),
+    Label(String),
+}
+
+impl Selector {
+    fn parse(s: &str) -> Self {
+        if s == "*" {
+            return Self::Any;
+        }
+
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            let bits = if ip.is_ipv4() { 32 } else { 128 };
+            return Self::Net(ip, bits);
+        }
+
+        if let Some((ip, bits)) = s.split_once('/') {
+            if let (Ok(ip), Ok(bits)) = (ip.parse::<IpAddr>(), bits.parse()) {
+                if bits <= if ip.is_ipv4() { 32 } else { 128 } {
+                    return Self::Net(ip, bits);
+                }
+            }
+        }
+
+        parse_mac(s).map_or_else(|| Self::Label(s.to_string()), Self::Mac)
+    }
+
+    fn matches(&self, host: &Host, labels: &HashMap<String, Vec<Self>>) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Mac(mac) => host.mac() == Some(*mac),
+            Self::Label(label) => labels
+                .get(label)
+                .is_some_and(|selectors| selectors.iter().any(|s| s.matches(host, labels))),
+
+            Self::Net(IpAddr::V4(net), bits) => match host.ip {
+                IpAddr::V4(ip) => {
+                    let mask = u32::MAX.checked_shl(32 - u32::from(*bits)).unwrap_or(0);
+                    u32::from(ip) & mask == u32::from(*net) & mask
+                }
+                IpAddr::V6(..) => false,
+            },
+
+            Self::Net(IpAddr::V6(net), bits) => match host.ip {
+                IpAddr::V6(ip) => {
+                    let mask = u128::MAX.checked_shl(128 - u32::from(*bits)).unwrap_or(0);
+                    u128::from(ip) & mask == u128::from(*net) & mask
+                }
+                IpAddr::V4(..) => false,
+            },
+        }
+    }
+}
+
+/// A host requesting a job
+///
+/// The host remembers its MAC address and which selectors it matches, so
+/// one `Host` should be used for all the jobs considered for a request.
+pub struct Host {
+    ip: IpAddr,
+    mac: OnceCell<Option<Mac>>,
+    selected: RefCell<HashMap<String, bool>>,
+}
+
+impl Host {
+    pub fn new(ip: IpAddr) -> Self {
+        Self {
+            ip,
+            mac: OnceCell::new(),
+            selected: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub const fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    /// The MAC address of the host (looked up on first use)
+    fn mac(&self) -> Option<Mac> {
+        *self.mac.get_or_init(|| arp(self.ip))
+    }
+
+    /// Whether the host matches a selector (parsed on first use)
+    fn selected(&self, selector: &str, labels: &HashMap<String, Vec<Selector>>) -> bool {
+        if let Some(&matched) = self.selected.borrow().get(selector) {
+            return matched;
+        }
+
+        let matched = Selector::parse(selector).matches(self, labels);
+        self.selected
+            .borrow_mut()
+            .insert(selector.to_string(), matched);
+        matched
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    Allow(Vec<String>),
+    Deny(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+struct RawRule {
+    hosts: Vec<String>,
+
+    #[serde(flatten)]
+    action: Action,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawRules {
+    #[serde(default)]
+    labels: HashMap<String, Vec<String>>,
+
+    #[serde(default)]
+    rules: Vec<RawRule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    hosts: Vec<Selector>,
+    allow: bool,
+    assets: Vec<String>,
+}
+
+/// Rules deciding which hosts may run which assets
+///
+/// The rules are checked in order and the first rule that matches both the
+/// host and the asset name decides whether the host may run the asset. If
+/// no rule matches, the host may run the asset.
+#[derive(Debug, Default)]
+pub struct Rules {
+    labels: HashMap<String, Vec<Selector>>,
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    /// Load the rules from a JSON file
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open rules file {}", path.display()))?;
+
+        let raw: RawRules = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Failed to parse rules file {}", path.display()))?;
+
+        // Labels may be used in rules, but not in other labels.
+        let parse = |hosts: &[String], labels: bool| -> Result<Vec<Selector>> {
+            hosts
+                .iter()
+                .map(|host| match Selector::parse(host) {
+                    Selector::Label(label) if !labels || !raw.labels.contains_key(&label) => {
+                        anyhow::bail!("Unknown host in {}: {label}", path.display())
+                    }
+                    selector => Ok(selector),
+                })
+                .collect()
+        };
+
+        let mut rules = Vec::new();
+        for rule in &raw.rules {
+            let (allow, assets) = match &rule.action {
+                Action::Allow(assets) => (true, assets.clone()),
+                Action::Deny(assets) => (false, assets.clone()),
+            };
+
+            rules.push(Rule {
+                hosts: parse(&rule.hosts, true)?,
+                allow,
+                assets,
+            });
+        }
+
+        let mut labels = HashMap::new();
+        for (label, hosts) in &raw.labels {
+            labels.insert(label.clone(), parse(hosts, false)?);
+        }
+
+        Ok(Self { labels, rules })
+    }
+
+    /// Check that every host in a list is a valid selector or a known label
+    pub fn check(&self, hosts: &[String]) -> Result<()> {
+        for host in hosts {
+            if let Selector::Label(label) = Selector::parse(host) {
+                if !self.labels.contains_key(&label) {
+                    anyhow::bail!("Unknown host: {label}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the host is one of the hosts (an empty list admits all)
+    pub fn admits(&self, host: &Host, hosts: &[String]) -> bool {
+        hosts.is_empty() || hosts.iter().any(|s| host.selected(s, &self.labels))
+    }
+
+    /// Whether the host may run the named asset
+    pub fn allows(&self, host: &Host, asset: &str) -> bool {
+        self.rules
+            .iter()
+            .find(|rule| {
+                rule.assets
+                    .iter()
+                    .any(|pattern| glob::matches(pattern, asset))
+                    && rule.hosts.iter().any(|s| s.matches(host, &self.labels))
+            })
+            .is_none_or(|rule| rule.allow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(selector: &str, ip: &str) -> bool {
+        let host = Host::new(ip.parse().unwrap());
+        Selector::parse(selector).matches(&host, &HashMap::new())
+    }
+
+    #[test]
+    fn network() {
+        assert!(matches("10.0.2.0/24", "10.0.2.17"));
+        assert!(!matches("10.0.2.0/24", "10.0.3.17"));
+        assert!(!matches("10.0.2.0/24", "::1"));
+    }
+
+    #[test]
+    fn whole() {
+        assert!(matches("0.0.0.0/0", "10.0.2.17"));
+        assert!(matches("0.0.0.0/0", "255.255.255.255"));
+        assert!(!matches("0.0.0.0/0", "::1"));
+        assert!(matches("::/0", "fe80::1"));
+    }
+
+    #[test]
+    fn single() {
+        assert!(matches("10.0.2.17/32", "10.0.2.17"));
+        assert!(!matches("10.0.2.17/32", "10.0.2.16"));
+        assert!(matches("10.0.2.17", "10.0.2.17"));
+        assert!(matches("fe80::1/128", "fe80::1"));
+        assert!(!matches("fe80::1/128", "fe80::2"));
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(
+            Selector::parse("10.0.2.0/33"),
+            Selector::Label("10.0.2.0/33".into())
+        );
+        assert_eq!(
+            Selector::parse("00:11:22:33:44:55"),
+            Selector::Mac([0, 0x11, 0x22, 0x33, 0x44, 0x55])
+        );
+    }
+}
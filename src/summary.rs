@@ -0,0 +1,289 @@
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::jobs::{Job, Jobs, State};
+
+/// The formats a summary can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Markdown,
+    Junit,
+}
+
+/// Parse a summary file name, picking the format from its extension
+pub fn file(s: &str) -> Result<(PathBuf, Format), String> {
+    let path = PathBuf::from(s);
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => Ok((path, Format::Json)),
+        Some("md") => Ok((path, Format::Markdown)),
+        Some("xml") => Ok((path, Format::Junit)),
+        _ => Err(format!("expected a .json, .md or .xml file: {s}")),
+    }
+}
+
+/// Escape text for use in XML
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+
+            // Other control characters aren't allowed in XML at all.
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// The outcome of a job
+#[derive(Debug, Serialize)]
+struct Outcome {
+    name: String,
+    source: Option<String>,
+    run: u32,
+
+    #[serde(skip)]
+    runs: u32,
+
+    /// The host the job belongs to (in `--each-host` mode)
+    host: Option<IpAddr>,
+
+    state: &'static str,
+    ip: Option<IpAddr>,
+    failure: Option<String>,
+    attempts: u32,
+
+    /// Seconds from the first assignment to the last state change
+    duration: Option<u64>,
+
+    report: Option<String>,
+    body: Option<String>,
+    issue: Option<String>,
+}
+
+impl From<&Job> for Outcome {
+    fn from(job: &Job) -> Self {
+        let duration = job
+            .history
+            .first()
+            .zip(job.history.last())
+            .and_then(|(first, last)| last.time.duration_since(first.time).ok())
+            .map(|duration| duration.as_secs());
+
+        Self {
+            name: job.asset.name.clone(),
+            source: job.asset.source.as_ref().map(ToString::to_string),
+            run: job.run,
+            runs: job.runs,
+            host: job.host,
+            state: job.state.name(),
+            ip: job.state.ip(),
+            failure: match job.state {
+                State::Failed(.., failure) => Some(failure.to_string()),
+                _ => None,
+            },
+            attempts: job.attempt,
+            duration,
+            report: job.report.clone(),
+            body: job.body.clone(),
+            issue: job.issue.clone(),
+        }
+    }
+}
+
+impl Outcome {
+    fn name(&self) -> String {
+        if self.runs > 1 {
+            format!("{} #{}", self.name, self.run)
+        } else {
+            self.name.clone()
+        }
+    }
+
+    fn cells(&self) -> [String; 6] {
+        [
+            self.name(),
+            self.state.to_string(),
+            self.ip.map_or_else(String::new, |ip| ip.to_string()),
+            self.failure.clone().unwrap_or_default(),
+            self.attempts.to_string(),
+            self.duration.map_or_else(String::new, |secs| {
+                format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+            }),
+        ]
+    }
+}
+
+/// A summary of the outcomes of every job in a run
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    finished: usize,
+    failed: usize,
+    cancelled: usize,
+    unfinished: usize,
+    jobs: Vec<Outcome>,
+}
+
+impl From<&Jobs> for Summary {
+    fn from(jobs: &Jobs) -> Self {
+        let mut summary = Self {
+            finished: 0,
+            failed: 0,
+            cancelled: 0,
+            unfinished: 0,
+            jobs: Vec::new(),
+        };
+
+        for job in jobs.iter() {
+            match job.state {
+                State::Finished(..) => summary.finished += 1,
+                State::Failed(..) => summary.failed += 1,
+                State::Cancelled => summary.cancelled += 1,
+                _ => summary.unfinished += 1,
+            }
+
+            summary.jobs.push(job.into());
+        }
+
+        summary
+    }
+}
+
+impl Summary {
+    const HEADER: [&str; 6] = ["Job", "State", "IP", "Failure", "Attempts", "Duration"];
+
+    /// The summary as a plain text table
+    pub fn table(&self) -> String {
+        let rows = self.jobs.iter().map(Outcome::cells).collect::<Vec<_>>();
+
+        let mut widths = Self::HEADER.map(str::len);
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let mut text = String::new();
+        let header = Self::HEADER.map(String::from);
+        for row in std::iter::once(&header).chain(&rows) {
+            let mut line = String::new();
+            for (cell, width) in row.iter().zip(widths) {
+                let _ = write!(line, "{cell:<width$}  ");
+            }
+
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+
+        let _ = writeln!(text, "\n{}", self.totals());
+        text
+    }
+
+    /// The summary as a Markdown table
+    pub fn markdown(&self) -> String {
+        let mut text = format!("| {} |\n", Self::HEADER.join(" | "));
+        let _ = writeln!(text, "|{}", "---|".repeat(Self::HEADER.len()));
+
+        for outcome in &self.jobs {
+            let _ = writeln!(text, "| {} |", outcome.cells().join(" | "));
+        }
+
+        let _ = writeln!(text, "\n{}", self.totals());
+        text
+    }
+
+    fn totals(&self) -> String {
+        format!(
+            "{} finished, {} failed, {} cancelled, {} unfinished",
+            self.finished, self.failed, self.cancelled, self.unfinished
+        )
+    }
+
+    /// The summary as a `JUnit` XML report, with one test case per job
+    ///
+    /// Failed jobs become failures and the body of each job's report becomes
+    /// its output. Cancelled and unfinished jobs are marked as skipped.
+    pub fn junit(&self) -> String {
+        let tests = self.jobs.len();
+        let skipped = self.cancelled + self.unfinished;
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(
+            xml,
+            "<testsuite name=\"dispatch\" tests=\"{tests}\" failures=\"{}\" skipped=\"{skipped}\">",
+            self.failed
+        );
+
+        for outcome in &self.jobs {
+            let mut name = outcome.name();
+            if let Some(host) = outcome.host {
+                let _ = write!(name, " on {host}");
+            }
+
+            let _ = write!(
+                xml,
+                "  <testcase classname=\"{}\" name=\"{}\" time=\"{}\">",
+                escape(outcome.source.as_deref().unwrap_or("dispatch")),
+                escape(&name),
+                outcome.duration.unwrap_or_default()
+            );
+
+            match (outcome.state, &outcome.failure) {
+                ("finished", ..) => {}
+                ("failed", failure) => {
+                    let message = failure.as_deref().unwrap_or_default();
+                    let ip = outcome.ip.map_or_else(String::new, |ip| ip.to_string());
+                    let _ = write!(
+                        xml,
+                        "\n    <failure message=\"{}\">Failed on {ip}</failure>",
+                        escape(message)
+                    );
+                }
+
+                (state, ..) => {
+                    let _ = write!(xml, "\n    <skipped message=\"{state}\"/>");
+                }
+            }
+
+            if let Some(body) = &outcome.body {
+                let _ = write!(xml, "\n    <system-out>{}</system-out>", escape(body));
+            }
+
+            if let Some(issue) = &outcome.issue {
+                let _ = write!(
+                    xml,
+                    "\n    <system-err>Issue: {}</system-err>",
+                    escape(issue)
+                );
+            }
+
+            xml.push_str("\n  </testcase>\n");
+        }
+
+        xml.push_str("</testsuite>\n");
+        xml
+    }
+
+    /// The summary in the given format
+    pub fn text(&self, format: Format) -> serde_json::Result<String> {
+        Ok(match format {
+            Format::Json => serde_json::to_string_pretty(self)?,
+            Format::Markdown => self.markdown(),
+            Format::Junit => self.junit(),
+        })
+    }
+}
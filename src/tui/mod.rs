@@ -4,15 +4,63 @@ mod throbbing;
 pub use status::Status;
 pub use throbbing::Throbbing;
 
+use std::fmt::{Display, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{LazyLock, Mutex};
 
+use chrono::{SecondsFormat, Utc};
 use crossterm::{cursor, execute};
 use ratatui::DefaultTerminal;
 
 pub static TERMINAL: LazyLock<Mutex<DefaultTerminal>> = LazyLock::new(|| ratatui::init().into());
 
-/// Cleanup function to restore terminal state
+static HEADLESS: AtomicBool = AtomicBool::new(false);
+static CLEANED: AtomicBool = AtomicBool::new(false);
+
+/// Run without the terminal UI, logging events to standard output instead
+pub fn headless() {
+    HEADLESS.store(true, Ordering::Relaxed);
+}
+
+/// Whether we are running without the terminal UI
+pub fn is_headless() -> bool {
+    HEADLESS.load(Ordering::Relaxed)
+}
+
+/// Log an event as a line of `key=value` pairs (only when headless)
+///
+/// Values containing spaces, quotes or `=` are quoted and empty values are
+/// left out.
+pub fn log(event: &str, fields: &[(&str, &dyn Display)]) {
+    if !is_headless() {
+        return;
+    }
+
+    let time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+    let mut line = format!("time={time} event={event}");
+
+    for (key, value) in fields {
+        let value = value.to_string();
+        if value.is_empty() {
+            continue;
+        }
+
+        if value.contains([' ', '"', '=']) {
+            let _ = write!(line, " {key}={value:?}");
+        } else {
+            let _ = write!(line, " {key}={value}");
+        }
+    }
+
+    println!("{line}");
+}
+
+/// Cleanup function to restore terminal state (only the first call has effect)
 pub fn cleanup() {
+    if is_headless() || CLEANED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+
     ratatui::restore();
     let _ = execute!(std::io::stdout(), cursor::Show);
 }
@@ -1,16 +1,22 @@
 use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::fs::File;
+use std::io::Write as _;
 use std::net::{IpAddr, SocketAddr};
 use std::ops::Deref;
 use std::ops::DerefMut;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use crossterm::event::KeyCode;
 use ratatui::layout::{Alignment, Constraint, Direction, Layout};
 use ratatui::style::{Color, Modifier, Style};
-use ratatui::widgets::{Cell, Paragraph, Row, Table};
+use ratatui::widgets::{Cell, Paragraph, Row, Table, TableState};
 
-use crate::github::Asset;
-use crate::jobs::{Job, Jobs, State};
+use crate::github::Contents;
+use crate::jobs::{Failure, Job, Jobs, JobsArgs, State};
+use crate::summary::{Format, Summary};
 
 #[allow(
     clippy::cast_precision_loss,
@@ -41,6 +47,7 @@ impl State {
             Self::Booting(..) => "⚡",
             Self::Reported(..) => "📝",
             Self::Failed(..) => "❌",
+            Self::Cancelled => "🚫",
             Self::Finished(..) => "🏁",
         }
     }
@@ -55,10 +62,20 @@ impl Job {
             State::Booting(..) => Style::default().fg(Color::Yellow),
             State::Failed(..) => Style::default().fg(Color::Red),
             State::Reported(..) => Style::default().fg(Color::Green),
+            State::Cancelled => Style::default().fg(Color::DarkGray),
         }
         .add_modifier(Modifier::BOLD)
     }
 
+    fn name(&self) -> String {
+        if self.runs > 1 {
+            format!("{} #{}", self.asset.name, self.run)
+        } else {
+            self.asset.name.clone()
+        }
+    }
+
+    #[allow(clippy::map_unwrap_or)]
     fn row(&self) -> Row<'static> {
         let style = self.style();
         let ip = self.state.ip().map(|ip| ip.to_string()).unwrap_or_default();
@@ -67,16 +84,67 @@ impl Job {
             .map(|time| DateTime::<Local>::from(time).format("%H:%M").to_string())
             .unwrap_or_default();
 
+        let failure = match self.state {
+            State::Failed(.., failure) => failure.to_string(),
+            _ => String::new(),
+        };
+
+        let source = self
+            .asset
+            .source
+            .as_ref()
+            .map_or_else(String::new, ToString::to_string);
+
+        let attempt = if self.attempts > 1 {
+            format!("{}/{}", self.attempt, self.attempts)
+        } else {
+            String::new()
+        };
+
         Row::new(vec![
             Cell::from(self.state.emoji()),
-            Cell::from(self.asset.name.clone()).style(style),
+            Cell::from(self.name()).style(style),
+            Cell::from(source).style(style),
             Cell::from(ip).style(style),
             Cell::from(seen).style(style),
+            Cell::from(attempt).style(style),
+            Cell::from(failure).style(style),
             Cell::from(format!("{:>10}", format_bytes(self.asset.size))).style(style),
         ])
     }
 }
 
+/// An action that an operator can take on the selected job
+#[derive(Debug, Clone, Copy)]
+enum Action {
+    Requeue,
+    Cancel,
+    Fail,
+    Pin,
+}
+
+/// An action on the selected job awaiting confirmation
+struct Prompt {
+    action: Action,
+    job: Job,
+
+    /// The host typed in so far (when pinning)
+    input: String,
+}
+
+impl Prompt {
+    fn text(&self) -> String {
+        let name = self.job.name();
+
+        match self.action {
+            Action::Requeue => format!("Requeue {name}? (y/n)"),
+            Action::Cancel => format!("Cancel {name}? (y/n)"),
+            Action::Fail => format!("Mark {name} as failed? (y/n)"),
+            Action::Pin => format!("Pin {name} to host: {}▏ (Esc to abort)", self.input),
+        }
+    }
+}
+
 pub struct Update<'a>(&'a mut Status);
 
 impl Deref for Update<'_> {
@@ -95,25 +163,208 @@ impl DerefMut for Update<'_> {
 
 impl Drop for Update<'_> {
     fn drop(&mut self) {
+        self.0.persist();
         let _ = self.0.render();
     }
 }
 
+/// Replace a file with new contents
+///
+/// The contents are written to a temporary file which then replaces the
+/// file. This ensures that a crash in the middle of writing never leaves a
+/// truncated file behind.
+fn replace(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+
+    std::fs::rename(tmp, path)
+}
+
+/// Writes the state file and summaries, one save at a time
+///
+/// Saves made in the background may finish out of order, so every save has
+/// a version and is skipped if a newer one has already been written.
+#[derive(Clone, Default)]
+struct Saver {
+    /// The version of the last save written (locked while writing)
+    written: Arc<Mutex<u64>>,
+
+    /// The errors of background saves that haven't been shown yet
+    failures: Arc<Mutex<Vec<String>>>,
+}
+
+impl Saver {
+    fn write(&self, version: u64, files: &[(PathBuf, Vec<u8>)]) -> std::io::Result<()> {
+        let mut written = self.written.lock().unwrap();
+        if *written >= version {
+            return Ok(());
+        }
+
+        for (path, contents) in files {
+            replace(path, contents).map_err(|error| {
+                let message = format!("Failed to write {}: {error}", path.display());
+                std::io::Error::new(error.kind(), message)
+            })?;
+        }
+
+        *written = version;
+        drop(written);
+        Ok(())
+    }
+}
+
 pub struct Status {
     jobs: Jobs,
     addr: SocketAddr,
     path: Arc<String>,
+    state: Option<PathBuf>,
+    summaries: Vec<(PathBuf, Format)>,
+    hits: usize,
+    misses: usize,
+
+    /// The index of the selected row of the table
+    selected: usize,
+    prompt: Option<Prompt>,
+
+    /// The job whose details are shown instead of the table
+    detail: Option<Job>,
+
+    /// The number of transitions of each job logged so far (when headless)
+    logged: Vec<usize>,
+
+    /// Problems to bring to the operator's attention
+    warnings: Vec<String>,
+
+    saver: Saver,
+
+    /// The version of the last save started
+    version: u64,
+
+    /// The number of changes to the jobs at the last save
+    saved: usize,
 }
 
 impl Status {
-    pub fn new(assets: BTreeSet<Asset>, addr: SocketAddr, path: Arc<String>) -> Self {
+    pub fn new(
+        jobs: Jobs,
+        addr: SocketAddr,
+        path: Arc<String>,
+        state: Option<PathBuf>,
+        summaries: Vec<(PathBuf, Format)>,
+    ) -> Self {
         Self {
-            jobs: Jobs::from(assets),
+            jobs,
             addr,
             path,
+            state,
+            summaries,
+            hits: 0,
+            misses: 0,
+            selected: 0,
+            prompt: None,
+            detail: None,
+            logged: Vec::new(),
+            warnings: Vec::new(),
+            saver: Saver::default(),
+            version: 0,
+            saved: 0,
         }
     }
 
+    pub const fn jobs(&self) -> &Jobs {
+        &self.jobs
+    }
+
+    /// Show a warning to the operator (unless it has been shown already)
+    pub fn warn(&mut self, warning: String) {
+        if self.warnings.contains(&warning) {
+            return;
+        }
+
+        super::log("warning", &[("msg", &warning)]);
+        self.warnings.push(warning);
+    }
+
+    /// Add jobs for the new assets of the releases, warning of any problems
+    pub fn add(&mut self, releases: &[Contents], args: &JobsArgs) {
+        for release in releases {
+            for warning in &release.warnings {
+                self.warn(warning.clone());
+            }
+        }
+
+        let added = self.update().extend(releases, args);
+        match added {
+            Ok(0) => {}
+            Ok(count) => super::log("added", &[("jobs", &count)]),
+            Err(error) => self.warn(format!("Failed to add new jobs: {error:#}")),
+        }
+    }
+
+    /// Record whether a download was served from the asset cache
+    pub const fn cached(&mut self, hit: bool) {
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+    }
+
+    /// The contents of the state file and the summaries (if any)
+    fn files(&self) -> std::io::Result<Vec<(PathBuf, Vec<u8>)>> {
+        let mut files = Vec::new();
+        if let Some(path) = &self.state {
+            files.push((path.clone(), serde_json::to_vec(&self.jobs)?));
+        }
+
+        if !self.summaries.is_empty() {
+            let summary = Summary::from(&self.jobs);
+            for (path, format) in &self.summaries {
+                files.push((path.clone(), summary.text(*format)?.into_bytes()));
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Persist the jobs to the state file and the summaries (if any)
+    pub fn save(&mut self) -> std::io::Result<()> {
+        self.saved = self.jobs.changes();
+        self.version += 1;
+        self.saver.write(self.version, &self.files()?)
+    }
+
+    /// Save in the background, if the jobs have changed since the last save
+    fn persist(&mut self) {
+        let failures = std::mem::take(&mut *self.saver.failures.lock().unwrap());
+        for failure in failures {
+            self.warn(failure);
+        }
+
+        let changes = self.jobs.changes();
+        if changes == self.saved {
+            return;
+        }
+
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(error) => return self.warn(format!("Failed to save: {error}")),
+        };
+
+        self.saved = changes;
+        self.version += 1;
+
+        let (saver, version) = (self.saver.clone(), self.version);
+        tokio::task::spawn_blocking(move || {
+            if let Err(error) = saver.write(version, &files) {
+                saver.failures.lock().unwrap().push(error.to_string());
+            }
+        });
+    }
+
     fn counts(&self) -> Paragraph<'static> {
         let mut unassigned = 0;
         let mut assigned = 0;
@@ -122,6 +373,7 @@ impl Status {
         let mut reported = 0;
         let mut finished = 0;
         let mut failed = 0;
+        let mut cancelled = 0;
 
         for job in self.jobs.iter() {
             match job.state {
@@ -132,12 +384,13 @@ impl Status {
                 State::Reported(..) => reported += 1,
                 State::Failed(..) => failed += 1,
                 State::Finished(..) => finished += 1,
+                State::Cancelled => cancelled += 1,
             }
         }
 
         let ip = IpAddr::V4([0, 0, 0, 0].into());
         let text = format!(
-            "{} {} {} {} {} {} {} {} {} {} {} {} {} {} 🚪 q",
+            "{} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} │ ⏎ details  r requeue  c cancel  f fail  p pin  🚪 q",
             State::Unassigned.emoji(),
             unassigned,
             State::Assigned(ip).emoji(),
@@ -150,8 +403,10 @@ impl Status {
             reported,
             State::Finished(ip).emoji(),
             finished,
-            State::Failed(ip).emoji(),
+            State::Failed(ip, Failure::Timeout).emoji(),
             failed,
+            State::Cancelled.emoji(),
+            cancelled,
         );
 
         Paragraph::new(text)
@@ -159,24 +414,218 @@ impl Status {
             .alignment(Alignment::Center)
     }
 
+    /// Whether a prompt is waiting for an answer
+    pub const fn prompting(&self) -> bool {
+        self.prompt.is_some()
+    }
+
+    /// Handle a key press in the table or in the prompt
+    pub fn key(&mut self, code: KeyCode) -> std::io::Result<()> {
+        match self.prompt.take() {
+            Some(prompt) => self.answer(prompt, code),
+            None if self.detail.is_some() => {
+                if matches!(code, KeyCode::Esc | KeyCode::Enter) {
+                    self.detail = None;
+                }
+            }
+
+            None => self.select(code),
+        }
+
+        self.render()
+    }
+
+    /// Move the selection or prompt for an action on the selected job
+    fn select(&mut self, code: KeyCode) {
+        let jobs = self.visible();
+        let last = jobs.len().saturating_sub(1);
+
+        let action = match code {
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.selected = self.selected.min(last).saturating_sub(1);
+                return;
+            }
+
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(last);
+                return;
+            }
+
+            KeyCode::Enter => {
+                self.detail = jobs.get(self.selected.min(last)).copied().cloned();
+                return;
+            }
+
+            KeyCode::Char('r') => Action::Requeue,
+            KeyCode::Char('c') => Action::Cancel,
+            KeyCode::Char('f') => Action::Fail,
+            KeyCode::Char('p') => Action::Pin,
+            _ => return,
+        };
+
+        if let Some(job) = jobs.get(self.selected.min(last)) {
+            self.prompt = Some(Prompt {
+                action,
+                job: (*job).clone(),
+                input: String::new(),
+            });
+        }
+    }
+
+    /// Answer the prompt, performing its action once confirmed
+    fn answer(&mut self, mut prompt: Prompt, code: KeyCode) {
+        let selected = prompt.job.clone();
+        let matches = move |job: &Job| job.same(&selected);
+
+        match (prompt.action, code) {
+            (Action::Pin, KeyCode::Enter) => match prompt.input.trim().parse() {
+                Ok(ip) => {
+                    self.update().pin(matches, ip);
+                }
+
+                Err(..) => self.prompt = Some(prompt),
+            },
+
+            (Action::Pin, KeyCode::Backspace) => {
+                prompt.input.pop();
+                self.prompt = Some(prompt);
+            }
+
+            (Action::Pin, KeyCode::Char(c)) => {
+                prompt.input.push(c);
+                self.prompt = Some(prompt);
+            }
+
+            (Action::Requeue, KeyCode::Char('y') | KeyCode::Enter) => {
+                self.update().requeue(matches);
+            }
+
+            (Action::Cancel, KeyCode::Char('y') | KeyCode::Enter) => {
+                self.update().cancel(matches);
+            }
+
+            (Action::Fail, KeyCode::Char('y') | KeyCode::Enter) => {
+                self.update().fail(matches);
+            }
+
+            (_, KeyCode::Esc | KeyCode::Char('n')) => {}
+            _ => self.prompt = Some(prompt),
+        }
+    }
+
     fn url(&self) -> Paragraph<'static> {
-        Paragraph::new(format!("🌐 http://{}{}", self.addr, self.path))
+        if let Some(prompt) = &self.prompt {
+            return Paragraph::new(prompt.text())
+                .style(Style::default().fg(Color::Yellow))
+                .alignment(Alignment::Left);
+        }
+
+        let mut text = format!("🌐 http://{}{}", self.addr, self.path);
+        if self.hits + self.misses > 0 {
+            let _ = write!(text, "  💾 {} hit {} miss", self.hits, self.misses);
+        }
+
+        if self.jobs.draining() {
+            text.push_str("  🚰 draining");
+        } else if self.jobs.paused() {
+            text.push_str("  ⏸️ paused");
+        }
+
+        let color = self.warnings.last().map_or(Color::White, |warning| {
+            let _ = write!(text, "  ⚠️ {warning}");
+            Color::Yellow
+        });
+
+        Paragraph::new(text)
+            .style(Style::default().fg(color))
+            .alignment(Alignment::Left)
+    }
+
+    /// The releases the jobs come from
+    fn releases(&self) -> Paragraph<'static> {
+        let sources = self
+            .jobs
+            .iter()
+            .filter_map(|job| job.asset.source.as_ref())
+            .map(ToString::to_string)
+            .collect::<BTreeSet<_>>();
+
+        let text = sources.into_iter().collect::<Vec<_>>().join("  ");
+        Paragraph::new(format!("🏷️ {text}"))
             .style(Style::default().fg(Color::White))
             .alignment(Alignment::Left)
     }
 
-    fn table(&self) -> Table<'static> {
+    /// The details of a job, including every state it has been in
+    fn detail(job: &Job) -> Paragraph<'static> {
+        let mut text = format!("{} {}\n\n", job.state.emoji(), job.name());
+
+        let _ = writeln!(text, "Size:        {}", format_bytes(job.asset.size));
+        let _ = writeln!(text, "Downloaded:  {}", format_bytes(job.downloaded));
+        let _ = writeln!(text, "Attempt:     {}/{}", job.attempt, job.attempts);
+
+        if let State::Failed(.., failure) = job.state {
+            let _ = writeln!(text, "Failure:     {failure}");
+        }
+
+        if let Some(report) = &job.report {
+            let _ = writeln!(text, "Report:      {report}");
+        }
+
+        if let Some(issue) = &job.issue {
+            let _ = writeln!(text, "Issue:       {issue}");
+        }
+
+        text.push_str("\nHistory:\n");
+        for transition in &job.history {
+            let time = DateTime::<Local>::from(transition.time).format("%Y-%m-%d %H:%M:%S");
+            let ip = transition
+                .state
+                .ip()
+                .map_or_else(String::new, |ip| ip.to_string());
+
+            let _ = write!(
+                text,
+                "  {time}  {} {:<12} {ip}",
+                transition.state.emoji(),
+                transition.state.name(),
+            );
+
+            if let State::Failed(.., failure) = transition.state {
+                let _ = write!(text, " ({failure})");
+            }
+
+            text.push('\n');
+        }
+
+        text.push_str("\nEsc to go back");
+        Paragraph::new(text).style(Style::default().fg(Color::White))
+    }
+
+    /// The jobs shown in the table, in order
+    fn visible(&self) -> Vec<&Job> {
         let jobs: BTreeSet<&Job> = self
             .jobs
             .iter()
             .filter(|job| !matches!(job.state, State::Finished(..)))
             .collect();
 
+        jobs.into_iter().collect()
+    }
+
+    fn table(&self) -> (Table<'static>, TableState) {
+        let jobs = self.visible();
+        let selected = self.selected.min(jobs.len().saturating_sub(1));
+        let state = TableState::default().with_selected((!jobs.is_empty()).then_some(selected));
+
         let cells = vec![
             Cell::from(""),
             Cell::from("Job Name"),
+            Cell::from("Source"),
             Cell::from("Assigned To"),
             Cell::from("Seen"),
+            Cell::from("Attempt"),
+            Cell::from("Failure"),
             Cell::from(format!("{:>10}", "Size")),
         ];
 
@@ -189,34 +638,104 @@ impl Status {
         let widths = [
             Constraint::Length(2),  // Status column
             Constraint::Min(20),    // Job Name column (flexible)
+            Constraint::Min(15),    // Source column (flexible)
             Constraint::Min(15),    // IP Address column (flexible)
             Constraint::Length(5),  // Last Seen column
+            Constraint::Length(7),  // Attempt column
+            Constraint::Length(25), // Failure column
             Constraint::Length(10), // Size column
         ];
 
         let rows = jobs.into_iter().map(Job::row);
-        Table::new(rows, widths).header(header).column_spacing(1)
+        let table = Table::new(rows, widths)
+            .header(header)
+            .column_spacing(1)
+            .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+        (table, state)
     }
 
-    pub fn render(&self) -> std::io::Result<()> {
+    /// Log the job transitions that haven't been logged yet
+    fn log(&mut self) {
+        self.logged.resize(self.jobs.iter().count(), 0);
+
+        for (job, logged) in self.jobs.iter().zip(&mut self.logged) {
+            let source = job
+                .asset
+                .source
+                .as_ref()
+                .map_or_else(String::new, ToString::to_string);
+
+            for transition in &job.history[*logged..] {
+                let time = DateTime::<Utc>::from(transition.time)
+                    .to_rfc3339_opts(SecondsFormat::Millis, true);
+                let ip = transition
+                    .state
+                    .ip()
+                    .map_or_else(String::new, |ip| ip.to_string());
+                let failure = match transition.state {
+                    State::Failed(.., failure) => failure.to_string(),
+                    _ => String::new(),
+                };
+
+                super::log(
+                    "transition",
+                    &[
+                        ("at", &time),
+                        ("job", &job.asset.name),
+                        ("source", &source),
+                        ("run", &job.run),
+                        ("state", &transition.state.name()),
+                        ("ip", &ip),
+                        ("failure", &failure),
+                        ("attempt", &job.attempt),
+                    ],
+                );
+            }
+
+            *logged = job.history.len();
+        }
+    }
+
+    pub fn render(&mut self) -> std::io::Result<()> {
+        if super::is_headless() {
+            self.log();
+            return Ok(());
+        }
+
         let vertical = Layout::default()
             .direction(Direction::Vertical)
             .margin(1)
-            .constraints([Constraint::Min(0), Constraint::Length(1)]);
+            .constraints([
+                Constraint::Length(1),
+                Constraint::Min(0),
+                Constraint::Length(1),
+            ]);
 
         let horizontal = Layout::default()
             .direction(Direction::Horizontal)
             .constraints([Constraint::Min(0), Constraint::Min(32)]);
 
+        let releases = self.releases();
         let url = self.url();
-        let table = self.table();
+        let (table, mut state) = self.table();
         let counts = self.counts();
 
+        // Show the latest details of the job, if it's still around.
+        let detail = self.detail.as_ref().map(|selected| {
+            let job = self.jobs.iter().find(|job| job.same(selected));
+            Self::detail(job.unwrap_or(selected))
+        });
+
         super::TERMINAL.lock().unwrap().draw(move |f| {
             let chunks = vertical.split(f.area());
-            f.render_widget(table, chunks[0]);
+            f.render_widget(releases, chunks[0]);
+            match detail {
+                Some(detail) => f.render_widget(detail, chunks[1]),
+                None => f.render_stateful_widget(table, chunks[1], &mut state),
+            }
 
-            let chunks = horizontal.split(chunks[1]);
+            let chunks = horizontal.split(chunks[2]);
             f.render_widget(url, chunks[0]);
             f.render_widget(counts, chunks[1]);
         })?;
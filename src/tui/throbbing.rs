@@ -20,6 +20,11 @@ where
     F: std::future::Future<Output = Result<T>>,
 {
     async fn throbbing(self, label: &str) -> Result<T> {
+        if super::is_headless() {
+            super::log("loading", &[("msg", &label)]);
+            return self.await;
+        }
+
         let start = Instant::now();
         let mut tick = 0;
         let mut interval = interval(Duration::from_millis(100));